@@ -0,0 +1,302 @@
+use crate::matrix4::Matrix4;
+use crate::quat::Quat;
+use crate::vector3::Vector3;
+use crate::vector4::Vector4;
+use crate::error::LoadError;
+use std::fs;
+
+#[derive(Clone, Copy, Debug)]
+pub struct Keyframe<T> {
+    pub time: f32,
+    pub value: T,
+}
+
+#[derive(Clone, Debug)]
+pub struct Joint {
+    pub parent: Option<usize>,
+    pub inverse_bind: Matrix4,
+    pub translations: Vec<Keyframe<Vector3>>,
+    pub rotations: Vec<Keyframe<Quat>>,
+    pub scales: Vec<Keyframe<Vector3>>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Skeleton {
+    pub joints: Vec<Joint>,
+    pub min_time: f32,
+    pub max_time: f32,
+}
+
+// Returns the keyframes surrounding `time` and the blend factor between them,
+// clamping to the first and last keys outside of the animated range.
+fn find_keys<T: Copy>(keys: &[Keyframe<T>], time: f32) -> Option<(T, T, f32)> {
+    let first = keys.first()?;
+    let last = keys.last()?;
+    if time <= first.time {
+        return Some((first.value, first.value, 0.0));
+    }
+    if time >= last.time {
+        return Some((last.value, last.value, 0.0));
+    }
+    let next = keys.iter().position(|key| key.time > time)?;
+    let prev = &keys[next - 1];
+    let next = &keys[next];
+    let t = (time - prev.time) / (next.time - prev.time);
+    Some((prev.value, next.value, t))
+}
+
+fn interpolate_vector3(keys: &[Keyframe<Vector3>], time: f32, default: Vector3) -> Vector3 {
+    match find_keys(keys, time) {
+        Some((a, b, t)) => a * (1.0 - t) + b * t,
+        None => default,
+    }
+}
+
+fn interpolate_quat(keys: &[Keyframe<Quat>], time: f32) -> Quat {
+    match find_keys(keys, time) {
+        Some((a, b, t)) => a.slerp(b, t),
+        None => Quat::identity(),
+    }
+}
+
+impl Joint {
+    pub fn local_matrix(&self, time: f32) -> Matrix4 {
+        let translation = interpolate_vector3(&self.translations, time, Vector3::zero());
+        let rotation = interpolate_quat(&self.rotations, time);
+        let scale = interpolate_vector3(&self.scales, time, Vector3::new(1.0, 1.0, 1.0));
+        Matrix4::from_translation(translation.x, translation.y, translation.z)
+            * rotation.to_mat4()
+            * Matrix4::from_scaling(scale.x, scale.y, scale.z)
+    }
+}
+
+impl Skeleton {
+    // Evaluates the pose at `time` (looping over the time range) and returns
+    // one skinning matrix per joint, mapping bind pose positions to posed ones.
+    pub fn joint_matrices(&self, time: f32) -> Vec<Matrix4> {
+        let duration = self.max_time - self.min_time;
+        let time = if duration > 0.0 {
+            self.min_time + time.rem_euclid(duration)
+        } else {
+            self.min_time
+        };
+        let mut world: Vec<Matrix4> = Vec::with_capacity(self.joints.len());
+        for joint in &self.joints {
+            let local = joint.local_matrix(time);
+            let matrix = match joint.parent {
+                Some(parent) => world[parent] * local,
+                None => local,
+            };
+            world.push(matrix);
+        }
+        world
+            .iter()
+            .zip(&self.joints)
+            .map(|(world, joint)| *world * joint.inverse_bind)
+            .collect()
+    }
+
+    pub fn load(path: &str) -> Result<Skeleton, LoadError> {
+        let source = fs::read_to_string(path).map_err(|e| LoadError::io(path, e))?;
+        Skeleton::parse(&source, path)
+    }
+
+    // `path` is only used to report errors.
+    pub fn parse(source: &str, path: &str) -> Result<Skeleton, LoadError> {
+        let mut lines = Lines {
+            lines: source
+                .lines()
+                .enumerate()
+                .map(|(number, line)| (number + 1, line.trim()))
+                .filter(|(_, line)| !line.is_empty())
+                .collect(),
+            current: 0,
+            path,
+        };
+
+        let joint_count = lines.count("joint-size")?;
+        let (number, range) = lines.value("time-range")?;
+        let (min_time, max_time) = match lines.floats(number, range)?[..] {
+            [min, max] => (min, max),
+            _ => return Err(lines.error(number, format!("invalid time range `{}`", range))),
+        };
+
+        let mut joints: Vec<Joint> = Vec::with_capacity(joint_count);
+        for index in 0..joint_count {
+            let header = format!("joint {}:", index);
+            let (number, line) = lines.next_line()?;
+            if line != header {
+                return Err(lines.error(number, format!("expected `{}` but found `{}`", header, line)));
+            }
+            let (number, parent) = lines.value("parent-index")?;
+            let parent = match parent.parse::<i64>() {
+                Ok(-1) => None,
+                Ok(parent) if parent >= 0 && (parent as usize) < index => Some(parent as usize),
+                Ok(parent) => {
+                    return Err(LoadError::IndexOutOfRange {
+                        path: path.to_string(),
+                        line: number,
+                        index: parent,
+                        count: index,
+                    })
+                }
+                Err(_) => return Err(lines.error(number, format!("invalid parent index `{}`", parent))),
+            };
+            lines.value("inverse-bind")?;
+            let mut rows = [Vector4::new(0.0, 0.0, 0.0, 0.0); 4];
+            for row in rows.iter_mut() {
+                let (number, line) = lines.next_line()?;
+                *row = match lines.floats(number, line)?[..] {
+                    [x, y, z, w] => Vector4::new(x, y, z, w),
+                    _ => return Err(lines.error(number, "expected 4 numbers")),
+                };
+            }
+            let inverse_bind = Matrix4::from_rows(&rows[0], &rows[1], &rows[2], &rows[3]);
+
+            let translations = lines.keyframes("translations", |v| match v[..] {
+                [x, y, z] => Some(Vector3::new(x, y, z)),
+                _ => None,
+            })?;
+            let rotations = lines.keyframes("rotations", |v| match v[..] {
+                [x, y, z, w] => Some(Quat::new(x, y, z, w)),
+                _ => None,
+            })?;
+            let scales = lines.keyframes("scales", |v| match v[..] {
+                [x, y, z] => Some(Vector3::new(x, y, z)),
+                _ => None,
+            })?;
+            joints.push(Joint {
+                parent,
+                inverse_bind,
+                translations,
+                rotations,
+                scales,
+            });
+        }
+        Ok(Skeleton {
+            joints,
+            min_time,
+            max_time,
+        })
+    }
+}
+
+struct Lines<'a> {
+    // non-empty trimmed lines with their 1-based line number
+    lines: Vec<(usize, &'a str)>,
+    current: usize,
+    path: &'a str,
+}
+
+impl<'a> Lines<'a> {
+    fn error(&self, line: usize, reason: impl Into<String>) -> LoadError {
+        LoadError::parse(self.path, line, reason)
+    }
+
+    fn next_line(&mut self) -> Result<(usize, &'a str), LoadError> {
+        let last = self.lines.last().map_or(0, |&(line, _)| line);
+        let line = self
+            .lines
+            .get(self.current)
+            .copied()
+            .ok_or_else(|| self.error(last, "unexpected end of animation file"))?;
+        self.current += 1;
+        Ok(line)
+    }
+
+    // Returns the text after `key:` on the next line.
+    fn value(&mut self, key: &str) -> Result<(usize, &'a str), LoadError> {
+        let (number, line) = self.next_line()?;
+        match line.split_once(':') {
+            Some((name, value)) if name.trim() == key => Ok((number, value.trim())),
+            _ => Err(self.error(number, format!("expected `{}` but found `{}`", key, line))),
+        }
+    }
+
+    fn count(&mut self, key: &str) -> Result<usize, LoadError> {
+        let (number, value) = self.value(key)?;
+        value
+            .parse::<usize>()
+            .map_err(|_| self.error(number, format!("invalid count `{}`", value)))
+    }
+
+    // Parses numbers separated by whitespace and/or commas, ignoring brackets.
+    fn floats(&self, number: usize, value: &str) -> Result<Vec<f32>, LoadError> {
+        value
+            .split(|c: char| c.is_whitespace() || c == ',' || c == '[' || c == ']')
+            .filter(|token| !token.is_empty())
+            .map(|token| {
+                token
+                    .parse::<f32>()
+                    .map_err(|_| self.error(number, format!("invalid number `{}`", token)))
+            })
+            .collect()
+    }
+
+    fn keyframes<T>(&mut self, name: &str, convert: impl Fn(Vec<f32>) -> Option<T>) -> Result<Vec<Keyframe<T>>, LoadError> {
+        let (number, header) = self.next_line()?;
+        let count = header
+            .strip_suffix(':')
+            .and_then(|h| h.strip_prefix(name))
+            .and_then(|count| count.trim().parse::<usize>().ok())
+            .ok_or_else(|| self.error(number, format!("expected `{} <count>:` but found `{}`", name, header)))?;
+        let mut keys = Vec::with_capacity(count);
+        for _ in 0..count {
+            // time: 0.000000, value: [x, y, z]
+            let (number, line) = self.next_line()?;
+            let invalid = || self.error(number, format!("invalid keyframe `{}`", line));
+            let (time, values) = line.split_once(", value:").ok_or_else(invalid)?;
+            let time = match time.split_once(':') {
+                Some(("time", time)) => self.floats(number, time)?,
+                _ => return Err(invalid()),
+            };
+            let value = convert(self.floats(number, values)?).ok_or_else(invalid)?;
+            match time[..] {
+                [time] => keys.push(Keyframe { time, value }),
+                _ => return Err(invalid()),
+            }
+        }
+        Ok(keys)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn interpolates_and_chains_joints() {
+        let source = "joint-size: 2
+time-range: [0.000000, 2.000000]
+
+joint 0:
+    parent-index: -1
+    inverse-bind:
+        1 0 0 0
+        0 1 0 0
+        0 0 1 0
+        0 0 0 1
+    translations 2:
+        time: 0.000000, value: [0.000000, 0.000000, 0.000000]
+        time: 2.000000, value: [2.000000, 0.000000, 0.000000]
+    rotations 0:
+    scales 0:
+
+joint 1:
+    parent-index: 0
+    inverse-bind:
+        1 0 0 0
+        0 1 0 -1
+        0 0 1 0
+        0 0 0 1
+    translations 1:
+        time: 0.000000, value: [0.000000, 1.000000, 0.000000]
+    rotations 0:
+    scales 0:
+";
+        let skeleton = Skeleton::parse(source, "test.ani").unwrap();
+        let matrices = skeleton.joint_matrices(1.0);
+        let p = matrices[1] * Vector4::new(0.0, 1.0, 0.0, 1.0);
+        assert_eq!(p, Vector4::new(1.0, 1.0, 0.0, 1.0));
+    }
+}
@@ -1,6 +1,5 @@
 use crate::matrix4::Matrix4;
 use crate::vector3::Vector3;
-use crate::vector4::Vector4;
 
 pub struct Camera {
     pub position: Vector3,
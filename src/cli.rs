@@ -0,0 +1,249 @@
+use trois::camera::Camera;
+use trois::framebuffer::FrameBuffer;
+use trois::material::NormalFormat;
+use trois::quat::Quat;
+use trois::renderer::Renderer;
+use trois::shader::{DebugView, Shading};
+use trois::texture::{Filter, MAX_ANISOTROPY};
+use trois::transform::Transform;
+use trois::vector3::Vector3;
+use trois::world::{World, SHADOW_BIAS, SHADOW_PCF_RADIUS};
+
+pub const USAGE: &str = "usage: trois render <scene> [options]
+
+options:
+    -o, --output <file>     image to write, .png, .tga or .hdr (default out.png)
+    --size <WxH>            image size in pixels (default 640x480)
+    --camera <x,y,z>        camera position (default 0,0,2.5)
+    --target <x,y,z>        point the camera looks at (default 0,0,0)
+    --fov <degrees>         vertical field of view (default 45)
+    --angle <degrees>       rotation of the scene around the y axis (default 0)
+    --time <seconds>        time of the animations (default 0)
+    --shading <name>        material, pbr, blinn, unlit, normal or texcoord (default material)
+    --filter <name>         nearest, bilinear, trilinear or anisotropic (default per texture)
+    --normal-format <name>  opengl or directx green channel of normal maps (default per material)
+    --shadow-bias <depth>   depth bias of the shadow lookups (default 0.001)
+    --shadow-pcf <radius>   PCF kernel radius in shadow map texels (default 1)
+    --threads <count>       rasterizer threads (default one per core)";
+
+#[derive(Debug, PartialEq)]
+pub struct Options {
+    pub scene: String,
+    pub output: String,
+    pub width: u32,
+    pub height: u32,
+    pub camera: Vector3,
+    pub target: Vector3,
+    pub fov: f32,
+    pub angle: f32,
+    pub time: f32,
+    pub shading: Shading,
+    pub filter: Option<Filter>,
+    pub normal_format: Option<NormalFormat>,
+    pub shadow_bias: f32,
+    pub shadow_pcf_radius: u32,
+    pub threads: Option<usize>,
+}
+
+fn parse_number(option: &str, value: &str) -> Result<f32, String> {
+    value
+        .parse::<f32>()
+        .map_err(|_| format!("invalid number `{}` for {}", value, option))
+}
+
+fn parse_vector(option: &str, value: &str) -> Result<Vector3, String> {
+    let values = value
+        .split(',')
+        .map(|v| parse_number(option, v.trim()))
+        .collect::<Result<Vec<f32>, String>>()?;
+    match values[..] {
+        [x, y, z] => Ok(Vector3::new(x, y, z)),
+        _ => Err(format!("expected x,y,z for {}, got `{}`", option, value)),
+    }
+}
+
+fn parse_size(value: &str) -> Result<(u32, u32), String> {
+    let size = value.split_once('x').and_then(|(width, height)| {
+        let width = width.parse::<u32>().ok().filter(|&width| width > 0)?;
+        let height = height.parse::<u32>().ok().filter(|&height| height > 0)?;
+        Some((width, height))
+    });
+    size.ok_or_else(|| format!("expected WxH for --size, got `{}`", value))
+}
+
+fn parse_shading(value: &str) -> Result<Shading, String> {
+    match value {
+        "material" => Ok(Shading::Material),
+        "pbr" => Ok(Shading::Pbr),
+        "blinn" => Ok(Shading::Blinn),
+        "unlit" => Ok(Shading::Unlit),
+        "normal" => Ok(Shading::Debug(DebugView::Normal)),
+        "texcoord" => Ok(Shading::Debug(DebugView::TexCoord)),
+        _ => Err(format!("unknown shading `{}`", value)),
+    }
+}
+
+fn parse_filter(value: &str) -> Result<Filter, String> {
+    match value {
+        "nearest" => Ok(Filter::Nearest),
+        "bilinear" => Ok(Filter::Bilinear),
+        "trilinear" => Ok(Filter::Trilinear),
+        "anisotropic" => Ok(Filter::Anisotropic(MAX_ANISOTROPY)),
+        _ => Err(format!("unknown filter `{}`", value)),
+    }
+}
+
+fn parse_normal_format(value: &str) -> Result<NormalFormat, String> {
+    match value {
+        "opengl" => Ok(NormalFormat::OpenGl),
+        "directx" => Ok(NormalFormat::DirectX),
+        _ => Err(format!("unknown normal map format `{}`", value)),
+    }
+}
+
+// Parses the arguments following `render`.
+pub fn parse_options(args: &[String]) -> Result<Options, String> {
+    let mut scene = None;
+    let mut options = Options {
+        scene: String::new(),
+        output: "out.png".to_string(),
+        width: 640,
+        height: 480,
+        camera: Vector3::new(0.0, 0.0, 2.5),
+        target: Vector3::zero(),
+        fov: 45.0,
+        angle: 0.0,
+        time: 0.0,
+        shading: Shading::Material,
+        filter: None,
+        normal_format: None,
+        shadow_bias: SHADOW_BIAS,
+        shadow_pcf_radius: SHADOW_PCF_RADIUS,
+        threads: None,
+    };
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if !arg.starts_with('-') {
+            if scene.replace(arg.clone()).is_some() {
+                return Err(format!("unexpected argument `{}`", arg));
+            }
+            continue;
+        }
+        let value = args.next().ok_or_else(|| format!("missing value for {}", arg))?;
+        match arg.as_str() {
+            "-o" | "--output" => options.output = value.clone(),
+            "--size" => (options.width, options.height) = parse_size(value)?,
+            "--camera" => options.camera = parse_vector(arg, value)?,
+            "--target" => options.target = parse_vector(arg, value)?,
+            "--fov" => options.fov = parse_number(arg, value)?,
+            "--angle" => options.angle = parse_number(arg, value)?,
+            "--time" => options.time = parse_number(arg, value)?,
+            "--shading" => options.shading = parse_shading(value)?,
+            "--filter" => options.filter = Some(parse_filter(value)?),
+            "--normal-format" => options.normal_format = Some(parse_normal_format(value)?),
+            "--shadow-bias" => options.shadow_bias = parse_number(arg, value)?,
+            "--shadow-pcf" => {
+                let radius = value.parse::<u32>().ok();
+                options.shadow_pcf_radius = radius.ok_or_else(|| format!("invalid PCF radius `{}`", value))?;
+            }
+            "--threads" => {
+                let threads = value.parse::<usize>().ok().filter(|&threads| threads > 0);
+                options.threads = Some(threads.ok_or_else(|| format!("invalid thread count `{}`", value))?);
+            }
+            _ => return Err(format!("unknown option `{}`", arg)),
+        }
+    }
+    options.scene = scene.ok_or_else(|| "missing scene file".to_string())?;
+    Ok(options)
+}
+
+// Renders a single frame offscreen and writes it to the output file.
+pub fn render(options: &Options) -> Result<(), String> {
+    let world = World::load(&options.scene).map_err(|e| e.to_string())?;
+    for warning in &world.warnings {
+        eprintln!("warning: {}", warning);
+    }
+    let camera = Camera::new(
+        options.camera,
+        options.target,
+        Vector3::new(0.0, 1.0, 0.0),
+        options.fov.to_radians(),
+        options.width as f32 / options.height as f32,
+        0.1,
+        100.0,
+    );
+    let mut stage = world.stage(&camera).map_err(|e| e.to_string())?;
+    stage.shading = options.shading;
+    stage.filter = options.filter;
+    stage.normal_format = options.normal_format;
+    if let Some(shadow_map) = stage.shadow_map.as_mut() {
+        shadow_map.bias = options.shadow_bias;
+        shadow_map.pcf_radius = options.shadow_pcf_radius;
+    }
+    let mut root = Transform::identity();
+    root.rotation = Quat::angle_axis(options.angle.to_radians(), &Vector3::new(0.0, 1.0, 0.0));
+    let mut renderer = Renderer::new();
+    if let Some(threads) = options.threads {
+        renderer.threads = threads;
+    }
+    let mut framebuffer = FrameBuffer::new(options.width, options.height);
+    world.draw(&mut stage, &camera, &mut renderer, root.to_mat4(), options.time, &mut framebuffer);
+    framebuffer
+        .save(&options.output)
+        .map_err(|e| format!("{}: {}", options.output, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use trois::cubemap::HdrImage;
+    use trois::math::linear_to_srgb;
+
+    fn args(line: &str) -> Vec<String> {
+        line.split_whitespace().map(String::from).collect()
+    }
+
+    #[test]
+    fn options_and_defaults() {
+        let options = parse_options(&args("scene.scn --size 1280x720 --camera 1,2,3 -o out.tga --shading normal --filter anisotropic --normal-format directx --shadow-pcf 3")).unwrap();
+        assert_eq!(options.scene, "scene.scn");
+        assert_eq!((options.width, options.height), (1280, 720));
+        assert_eq!(options.camera, Vector3::new(1.0, 2.0, 3.0));
+        assert_eq!(options.output, "out.tga");
+        assert_eq!(options.shading, Shading::Debug(DebugView::Normal));
+        assert_eq!(options.filter, Some(Filter::Anisotropic(16)));
+        assert_eq!(options.normal_format, Some(NormalFormat::DirectX));
+        assert_eq!(options.fov, 45.0);
+        assert_eq!(options.shadow_pcf_radius, 3);
+        assert_eq!(options.shadow_bias, SHADOW_BIAS);
+
+        assert!(parse_options(&args("--size 0x10 scene.scn")).is_err());
+        assert!(parse_options(&args("scene.scn --camera 1,2")).is_err());
+        assert!(parse_options(&args("--fov 30")).is_err());
+        assert!(parse_options(&args("scene.scn --shadow-pcf -1")).is_err());
+    }
+
+    #[test]
+    fn hdr_output_round_trips() {
+        let dir = std::env::temp_dir();
+        let path = |extension: &str| dir.join(format!("trois-render-{}.{}", std::process::id(), extension));
+        let (hdr, png) = (path("hdr"), path("png"));
+        for output in [&hdr, &png] {
+            let line = format!("assets/common/sphere.scn --size 32x24 -o {}", output.display());
+            render(&parse_options(&args(&line)).unwrap()).unwrap();
+        }
+        let hdr_image = HdrImage::load(&hdr.to_string_lossy()).unwrap();
+        let png_image = image::open(&png).unwrap().to_rgb8();
+        std::fs::remove_file(&hdr).unwrap();
+        std::fs::remove_file(&png).unwrap();
+
+        assert_eq!((hdr_image.width, hdr_image.height), (32, 24));
+        // the 8-bit output is the same image, encoded and clamped
+        for (hdr, png) in hdr_image.pixels.iter().zip(png_image.pixels()) {
+            for (linear, encoded) in [hdr.x, hdr.y, hdr.z].iter().zip(png.0) {
+                let expected = linear_to_srgb(linear.clamp(0.0, 1.0)) * 255.0;
+                assert!((expected - encoded as f32).abs() <= 3.0, "{} {}", expected, encoded);
+            }
+        }
+    }
+}
@@ -0,0 +1,104 @@
+use crate::vector4::Vector4;
+
+// Planes of the OpenGL clip volume, -w <= x, y, z <= w.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ClipPlane {
+    Near,
+    Far,
+    Left,
+    Right,
+    Bottom,
+    Top,
+}
+
+impl ClipPlane {
+    // Clipping against near and far is required for correct output, the
+    // side planes only save rasterizing outside of the viewport.
+    pub const DEPTH: [ClipPlane; 2] = [ClipPlane::Near, ClipPlane::Far];
+    pub const SIDES: [ClipPlane; 4] = [ClipPlane::Left, ClipPlane::Right, ClipPlane::Bottom, ClipPlane::Top];
+    pub const ALL: [ClipPlane; 6] = [
+        ClipPlane::Near,
+        ClipPlane::Far,
+        ClipPlane::Left,
+        ClipPlane::Right,
+        ClipPlane::Bottom,
+        ClipPlane::Top,
+    ];
+
+    // Signed distance of a clip space position, negative outside.
+    fn distance(&self, p: Vector4) -> f32 {
+        match self {
+            ClipPlane::Near => p.w + p.z,
+            ClipPlane::Far => p.w - p.z,
+            ClipPlane::Left => p.w + p.x,
+            ClipPlane::Right => p.w - p.x,
+            ClipPlane::Bottom => p.w + p.y,
+            ClipPlane::Top => p.w - p.y,
+        }
+    }
+}
+
+// Sutherland-Hodgman clipping of a convex polygon given in clip space.
+// New vertices get their varyings from `lerp(a, b, t)`. The result is empty
+// or has at least three vertices, in the winding of the input.
+pub fn clip_polygon<V: Copy>(
+    polygon: &[(Vector4, V)],
+    planes: &[ClipPlane],
+    lerp: impl Fn(&V, &V, f32) -> V,
+) -> Vec<(Vector4, V)> {
+    let mut output = polygon.to_vec();
+    for plane in planes {
+        if output.len() < 3 {
+            break;
+        }
+        let input = std::mem::take(&mut output);
+        for i in 0..input.len() {
+            let current = input[i];
+            let next = input[(i + 1) % input.len()];
+            let d0 = plane.distance(current.0);
+            let d1 = plane.distance(next.0);
+            if d0 >= 0.0 {
+                output.push(current);
+            }
+            if (d0 >= 0.0) != (d1 >= 0.0) {
+                let t = d0 / (d0 - d1);
+                let position = current.0 + (next.0 - current.0) * t;
+                output.push((position, lerp(&current.1, &next.1, t)));
+            }
+        }
+    }
+    if output.len() < 3 {
+        output.clear();
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lerp(a: &f32, b: &f32, t: f32) -> f32 {
+        a + (b - a) * t
+    }
+
+    #[test]
+    fn near_plane_splits_triangle() {
+        // one vertex behind the near plane, varyings are the view depth
+        let triangle = [
+            (Vector4::new(0.0, 0.0, -3.0, 1.0), 0.0),
+            (Vector4::new(1.0, 0.0, 0.0, 1.0), 1.0),
+            (Vector4::new(0.0, 1.0, 0.0, 1.0), 1.0),
+        ];
+        let clipped = clip_polygon(&triangle, &ClipPlane::DEPTH, lerp);
+        assert_eq!(clipped.len(), 4);
+        for (position, _) in &clipped {
+            assert!(position.z >= -position.w - 1e-6);
+        }
+        assert!((clipped[0].1 - 2.0 / 3.0).abs() < 1e-6);
+
+        let behind = triangle.map(|(position, v)| (Vector4::new(position.x, position.y, -2.0, 1.0), v));
+        assert!(clip_polygon(&behind, &ClipPlane::ALL, lerp).is_empty());
+        let inside = triangle.map(|(position, v)| (Vector4::new(position.x, position.y, 0.5, 1.0), v));
+        assert_eq!(clip_polygon(&inside, &ClipPlane::ALL, lerp).len(), 3);
+    }
+}
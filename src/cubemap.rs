@@ -0,0 +1,159 @@
+use crate::error::LoadError;
+use crate::vector2::Vector2;
+use crate::vector3::Vector3;
+use image::codecs::hdr::HdrDecoder;
+use std::fs::File;
+use std::io::BufReader;
+
+// Linear floating point image, as stored in Radiance .hdr files.
+#[derive(Clone, Debug)]
+pub struct HdrImage {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<Vector3>,
+}
+
+impl HdrImage {
+    pub fn load(path: &str) -> Result<HdrImage, LoadError> {
+        let file = File::open(path).map_err(|e| LoadError::io(path, e))?;
+        let decoder = HdrDecoder::new(BufReader::new(file)).map_err(|e| LoadError::image(path, e))?;
+        let metadata = decoder.metadata();
+        let pixels = decoder
+            .read_image_hdr()
+            .map_err(|e| LoadError::image(path, e))?
+            .iter()
+            .map(|pixel| Vector3::new(pixel[0], pixel[1], pixel[2]))
+            .collect();
+        Ok(HdrImage {
+            width: metadata.width,
+            height: metadata.height,
+            pixels,
+        })
+    }
+
+    pub fn get_pixel(&self, x: u32, y: u32) -> Vector3 {
+        self.pixels[(y * self.width + x) as usize]
+    }
+
+    // Bilinear lookup clamped to the edges, v = 0 is the first row.
+    pub fn sample(&self, uv: Vector2) -> Vector3 {
+        let x = (uv.x * self.width as f32 - 0.5).clamp(0.0, (self.width - 1) as f32);
+        let y = (uv.y * self.height as f32 - 0.5).clamp(0.0, (self.height - 1) as f32);
+        let (x0, y0) = (x as u32, y as u32);
+        let (x1, y1) = ((x0 + 1).min(self.width - 1), (y0 + 1).min(self.height - 1));
+        let (fx, fy) = (x - x0 as f32, y - y0 as f32);
+        let top = self.get_pixel(x0, y0) * (1.0 - fx) + self.get_pixel(x1, y0) * fx;
+        let bottom = self.get_pixel(x0, y1) * (1.0 - fx) + self.get_pixel(x1, y1) * fx;
+        top * (1.0 - fy) + bottom * fy
+    }
+}
+
+// Six square faces in the order +x, -x, +y, -y, +z, -z, laid out like
+// OpenGL cubemaps with the first row of every face at t = 0.
+#[derive(Clone, Debug)]
+pub struct Cubemap {
+    pub size: u32,
+    pub faces: Vec<HdrImage>,
+}
+
+pub const FACE_NAMES: [&str; 6] = ["px", "nx", "py", "ny", "pz", "nz"];
+
+// Face and texture coordinates in [0, 1] hit by a direction.
+fn select_face(direction: Vector3) -> (usize, f32, f32) {
+    let (ax, ay, az) = (direction.x.abs(), direction.y.abs(), direction.z.abs());
+    let (face, major, s, t) = if ax >= ay && ax >= az {
+        if direction.x > 0.0 {
+            (0, ax, -direction.z, -direction.y)
+        } else {
+            (1, ax, direction.z, -direction.y)
+        }
+    } else if ay >= az {
+        if direction.y > 0.0 {
+            (2, ay, direction.x, direction.z)
+        } else {
+            (3, ay, direction.x, -direction.z)
+        }
+    } else if direction.z > 0.0 {
+        (4, az, direction.x, -direction.y)
+    } else {
+        (5, az, -direction.x, -direction.y)
+    };
+    (face, (s / major + 1.0) * 0.5, (t / major + 1.0) * 0.5)
+}
+
+// Inverse of `select_face`, coordinates may lie outside of [0, 1].
+fn face_direction(face: usize, s: f32, t: f32) -> Vector3 {
+    let (s, t) = (s * 2.0 - 1.0, t * 2.0 - 1.0);
+    match face {
+        0 => Vector3::new(1.0, -t, -s),
+        1 => Vector3::new(-1.0, -t, s),
+        2 => Vector3::new(s, 1.0, t),
+        3 => Vector3::new(s, -1.0, -t),
+        4 => Vector3::new(s, -t, 1.0),
+        _ => Vector3::new(-s, -t, -1.0),
+    }
+}
+
+impl Cubemap {
+    // Loads `<prefix><face>.hdr` for every face, e.g. `assets/spruit/i_`.
+    pub fn load(prefix: &str) -> Result<Cubemap, LoadError> {
+        let faces = FACE_NAMES
+            .iter()
+            .map(|name| HdrImage::load(&format!("{}{}.hdr", prefix, name)))
+            .collect::<Result<Vec<_>, _>>()?;
+        let size = faces[0].width;
+        if let Some(face) = faces.iter().position(|face| face.width != size || face.height != size) {
+            return Err(LoadError::invalid(
+                &format!("{}{}.hdr", prefix, FACE_NAMES[face]),
+                format!("cubemap faces must all be {}x{}", size, size),
+            ));
+        }
+        Ok(Cubemap { size, faces })
+    }
+
+    // Texel of the face hit by the direction through the center of texel
+    // (x, y) of `face`, which may be outside of it.
+    fn texel(&self, face: usize, x: i64, y: i64) -> Vector3 {
+        let size = self.size as i64;
+        if x >= 0 && y >= 0 && x < size && y < size {
+            return self.faces[face].get_pixel(x as u32, y as u32);
+        }
+        let s = (x as f32 + 0.5) / self.size as f32;
+        let t = (y as f32 + 0.5) / self.size as f32;
+        let (face, s, t) = select_face(face_direction(face, s, t));
+        let last = self.size - 1;
+        let x = ((s * self.size as f32) as u32).min(last);
+        let y = ((t * self.size as f32) as u32).min(last);
+        self.faces[face].get_pixel(x, y)
+    }
+
+    // Bilinear lookup, taps falling off a face are fetched from the
+    // neighbouring one so that there are no visible seams.
+    pub fn sample(&self, direction: Vector3) -> Vector3 {
+        let (face, s, t) = select_face(direction);
+        let x = s * self.size as f32 - 0.5;
+        let y = t * self.size as f32 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as i64, y0 as i64);
+        let top = self.texel(face, x0, y0) * (1.0 - fx) + self.texel(face, x0 + 1, y0) * fx;
+        let bottom = self.texel(face, x0, y0 + 1) * (1.0 - fx) + self.texel(face, x0 + 1, y0 + 1) * fx;
+        top * (1.0 - fy) + bottom * fy
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn face_selection_round_trips() {
+        for face in 0..6 {
+            for &(s, t) in &[(0.5, 0.5), (0.1, 0.8), (0.95, 0.02)] {
+                let (found, u, v) = select_face(face_direction(face, s, t));
+                assert_eq!(found, face);
+                assert!((u - s).abs() < 1e-5 && (v - t).abs() < 1e-5);
+            }
+        }
+    }
+}
@@ -0,0 +1,103 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+#[derive(Debug)]
+pub enum LoadError {
+    Io {
+        path: String,
+        source: io::Error,
+    },
+    Parse {
+        path: String,
+        line: usize,
+        reason: String,
+    },
+    IndexOutOfRange {
+        path: String,
+        line: usize,
+        index: i64,
+        count: usize,
+    },
+    UnsupportedImage {
+        path: String,
+        source: image::ImageError,
+    },
+    // structurally broken files that have no meaningful line, such as a
+    // glTF accessor pointing past the end of its buffer
+    Invalid {
+        path: String,
+        reason: String,
+    },
+}
+
+impl LoadError {
+    pub fn io(path: &str, source: io::Error) -> LoadError {
+        LoadError::Io {
+            path: path.to_string(),
+            source,
+        }
+    }
+
+    pub fn parse(path: &str, line: usize, reason: impl Into<String>) -> LoadError {
+        LoadError::Parse {
+            path: path.to_string(),
+            line,
+            reason: reason.into(),
+        }
+    }
+
+    pub fn image(path: &str, source: image::ImageError) -> LoadError {
+        match source {
+            image::ImageError::IoError(source) => LoadError::io(path, source),
+            source => LoadError::UnsupportedImage {
+                path: path.to_string(),
+                source,
+            },
+        }
+    }
+
+    pub fn invalid(path: &str, reason: impl Into<String>) -> LoadError {
+        LoadError::Invalid {
+            path: path.to_string(),
+            reason: reason.into(),
+        }
+    }
+
+    pub fn path(&self) -> &str {
+        match self {
+            LoadError::Io { path, .. }
+            | LoadError::Parse { path, .. }
+            | LoadError::IndexOutOfRange { path, .. }
+            | LoadError::UnsupportedImage { path, .. }
+            | LoadError::Invalid { path, .. } => path,
+        }
+    }
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LoadError::Io { path, source } => write!(f, "{}: {}", path, source),
+            LoadError::Parse { path, line, reason } => write!(f, "{}:{}: {}", path, line, reason),
+            LoadError::IndexOutOfRange {
+                path,
+                line,
+                index,
+                count,
+            } => write!(f, "{}:{}: index {} out of range for {} elements", path, line, index, count),
+            LoadError::UnsupportedImage { path, source } => write!(f, "{}: {}", path, source),
+            LoadError::Invalid { path, reason } => write!(f, "{}: {}", path, reason),
+        }
+    }
+}
+
+impl Error for LoadError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            LoadError::Io { source, .. } => Some(source),
+            LoadError::UnsupportedImage { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
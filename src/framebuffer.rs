@@ -1,10 +1,20 @@
+use crate::math::linear_to_srgb;
+use crate::vector4::Vector4;
+use image::codecs::hdr::HdrEncoder;
+use image::{ImageError, Rgb, RgbImage};
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
 
 
 
+
+// Linear colors, kept as floats so that they are only quantized once on
+// output.
 pub struct FrameBuffer {
     width: u32,
     height: u32,
-    colors: Vec<u32>,
+    colors: Vec<Vector4>,
     depth: Vec<f32>,
 }
 
@@ -13,23 +23,31 @@ pub struct FrameBuffer {
 impl FrameBuffer {
     pub fn new(width: u32, height: u32) -> FrameBuffer {
         FrameBuffer {
-            width: width,
-            height: height,
-            colors: vec![0xFF000000; (width * height) as usize],
+            width,
+            height,
+            colors: vec![Vector4::new(0.0, 0.0, 0.0, 1.0); (width * height) as usize],
             depth: vec![1.0; (width * height) as usize],
         }
     }
 
-    pub fn set_color(&mut self, x: u32, y: u32, color: u32) {
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn set_color(&mut self, x: u32, y: u32, color: Vector4) {
         let index = (y * self.width + x) as usize;
         self.colors[index] = color;
     }
 
-    pub fn get_color(&self, x: u32, y: u32) -> u32 {
+    pub fn get_color(&self, x: u32, y: u32) -> Vector4 {
         self.colors[(y * self.width + x) as usize]
     }
 
-    pub fn clear(&mut self, color: u32) {
+    pub fn clear(&mut self, color: Vector4) {
         for i in 0..self.colors.len() {
             self.colors[i] = color;
         }
@@ -46,7 +64,71 @@ impl FrameBuffer {
         self.depth[(y * self.width + x) as usize] = depth;
     }
 
-    pub fn get_colors(&self) -> &[u32] {
+    pub fn get_colors(&self) -> &[Vector4] {
         &self.colors
     }
+
+    // sRGB encoded channels of a linear color, clamped to [0, 1].
+    fn channels(color: Vector4) -> [u8; 3] {
+        [color.x, color.y, color.z].map(|c| (linear_to_srgb(c.clamp(0.0, 1.0)) * 255.0).round() as u8)
+    }
+
+    // The colors ready for display, the alpha channel is kept.
+    pub fn srgb_colors(&self) -> Vec<u32> {
+        self.colors
+            .iter()
+            .map(|&color| {
+                let [r, g, b] = FrameBuffer::channels(color);
+                let a = (color.w.clamp(0.0, 1.0) * 255.0).round() as u32;
+                a << 24 | (r as u32) << 16 | (g as u32) << 8 | b as u32
+            })
+            .collect()
+    }
+
+    // Writes the colors to an image file, the format follows the extension.
+    // Radiance .hdr files get the linear colors unclamped, the others are
+    // 8-bit sRGB.
+    pub fn save(&self, path: &str) -> Result<(), ImageError> {
+        let is_hdr = Path::new(path)
+            .extension()
+            .is_some_and(|extension| extension.eq_ignore_ascii_case("hdr"));
+        if is_hdr {
+            let pixels: Vec<Rgb<f32>> = self.colors.iter().map(|color| Rgb([color.x, color.y, color.z])).collect();
+            let file = BufWriter::new(File::create(path)?);
+            return HdrEncoder::new(file).encode(&pixels, self.width as usize, self.height as usize);
+        }
+        let mut image = RgbImage::new(self.width, self.height);
+        for (pixel, &color) in image.pixels_mut().zip(self.colors.iter()) {
+            *pixel = Rgb(FrameBuffer::channels(color));
+        }
+        image.save(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cubemap::HdrImage;
+    use crate::vector3::Vector3;
+
+    #[test]
+    fn colors_are_encoded_for_display() {
+        let mut framebuffer = FrameBuffer::new(2, 1);
+        framebuffer.set_color(0, 0, Vector4::new(0.0, 0.0, 0.0, 1.0));
+        framebuffer.set_color(1, 0, Vector4::new(0.5, 2.0, 0.002, 1.0));
+        // linear 0.5 is about 0.73 in sRGB, values above one are clamped and
+        // dark values keep their precision
+        assert_eq!(framebuffer.srgb_colors(), vec![0xFF000000, 0xFFBAFF0F]);
+    }
+
+    #[test]
+    fn hdr_files_keep_bright_colors() {
+        let mut framebuffer = FrameBuffer::new(2, 1);
+        framebuffer.set_color(1, 0, Vector4::new(4.0, 0.5, 0.25, 1.0));
+        let path = std::env::temp_dir().join(format!("trois-framebuffer-{}.hdr", std::process::id()));
+        framebuffer.save(&path.to_string_lossy()).unwrap();
+        let image = HdrImage::load(&path.to_string_lossy()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(image.pixels, vec![Vector3::zero(), Vector3::new(4.0, 0.5, 0.25)]);
+    }
 }
@@ -0,0 +1,82 @@
+use crate::cubemap::{Cubemap, HdrImage, FACE_NAMES};
+use crate::error::LoadError;
+use crate::framebuffer::FrameBuffer;
+use crate::matrix4::Matrix4;
+use crate::vector2::Vector2;
+use crate::vector3::Vector3;
+use crate::vector4::Vector4;
+use std::path::Path;
+
+// Prefiltered environment lighting for the split-sum approximation.
+pub struct Environment {
+    pub irradiance: Cubemap,
+    // specular radiance prefiltered for increasing roughness
+    pub specular: Vec<Cubemap>,
+    // scale and bias applied to f0, indexed by (n.v, 1 - roughness)
+    pub brdf_lut: HdrImage,
+}
+
+impl Environment {
+    // Loads `i_*.hdr` and `m0_*.hdr`, `m1_*.hdr`... from `dir`. The BRDF
+    // table is shared by all environments and lives in `common/` next to it.
+    pub fn load(dir: &str) -> Result<Environment, LoadError> {
+        let dir = Path::new(dir);
+        let prefix = |name: &str| dir.join(name).to_string_lossy().into_owned();
+        let irradiance = Cubemap::load(&prefix("i_"))?;
+        let mut specular = Vec::new();
+        loop {
+            let level = prefix(&format!("m{}_", specular.len()));
+            if !specular.is_empty() && !Path::new(&format!("{}{}.hdr", level, FACE_NAMES[0])).exists() {
+                break;
+            }
+            specular.push(Cubemap::load(&level)?);
+        }
+        let brdf_lut = dir.with_file_name("common").join("brdf_lut.hdr");
+        let brdf_lut = HdrImage::load(&brdf_lut.to_string_lossy())?;
+        Ok(Environment {
+            irradiance,
+            specular,
+            brdf_lut,
+        })
+    }
+
+    pub fn diffuse(&self, normal: Vector3) -> Vector3 {
+        self.irradiance.sample(normal)
+    }
+
+    // Blends the two prefiltered levels closest to the roughness.
+    pub fn specular(&self, reflection: Vector3, roughness: f32) -> Vector3 {
+        let level = roughness.clamp(0.0, 1.0) * (self.specular.len() - 1) as f32;
+        let lower = level.floor() as usize;
+        let upper = (lower + 1).min(self.specular.len() - 1);
+        let t = level - lower as f32;
+        self.specular[lower].sample(reflection) * (1.0 - t) + self.specular[upper].sample(reflection) * t
+    }
+
+    // Returns the scale and bias of f0 in the split-sum approximation.
+    pub fn brdf(&self, n_dot_v: f32, roughness: f32) -> (f32, f32) {
+        let texel = self.brdf_lut.sample(Vector2::new(n_dot_v, 1.0 - roughness));
+        (texel.x, texel.y)
+    }
+
+    // Fills the pixels no model covered with the environment seen along the
+    // view ray through them, `blur` picks a prefiltered level as a roughness.
+    pub fn draw_skybox(&self, framebuffer: &mut FrameBuffer, blur: f32, projection: Matrix4, view_to_world: Matrix4) {
+        let clip_to_view = projection.inverse();
+        let (width, height) = (framebuffer.width(), framebuffer.height());
+        for y in 0..height {
+            for x in 0..width {
+                if framebuffer.get_depth(x, y) < 1.0 {
+                    continue;
+                }
+                let ndc_x = (x as f32 + 0.5) / width as f32 * 2.0 - 1.0;
+                let ndc_y = 1.0 - (y as f32 + 0.5) / height as f32 * 2.0;
+                let target = clip_to_view * Vector4::new(ndc_x, ndc_y, 1.0, 1.0);
+                let ray = (target.xyz() * (1.0 / target.w)).normalize();
+                let direction = (view_to_world * Vector4::new(ray.x, ray.y, ray.z, 0.0)).xyz();
+                let color = self.specular(direction, blur);
+                framebuffer.set_color(x, y, Vector4::from_vector3(color));
+            }
+        }
+    }
+}
@@ -0,0 +1,31 @@
+pub mod animation;
+pub mod camera;
+pub mod clip;
+pub mod cubemap;
+pub mod error;
+pub mod framebuffer;
+pub mod ibl;
+pub mod light;
+pub mod loader;
+pub mod material;
+pub mod math;
+pub mod matrix3;
+pub mod matrix4;
+pub mod mesh;
+pub mod mtl;
+pub mod normal;
+pub mod pbr;
+pub mod quat;
+pub mod raster;
+pub mod renderer;
+pub mod scene;
+pub mod shader;
+pub mod shadow;
+pub mod store;
+pub mod tangent;
+pub mod texture;
+pub mod transform;
+pub mod vector2;
+pub mod vector3;
+pub mod vector4;
+pub mod world;
@@ -1,4 +1,4 @@
-use crate::{transform::Transform, vector3::Vector3};
+use crate::{matrix4::Matrix4, quat::Quat, transform::Transform, vector3::Vector3};
 
 #[derive(Clone, Debug, Copy)]
 pub struct Light {
@@ -15,4 +15,23 @@ impl Light {
             transform,
         }
     }
+
+    // Lights shine along their local -z axis.
+    pub fn forward(&self) -> Vector3 {
+        self.transform.rotation * Vector3::new(0.0, 0.0, -1.0)
+    }
+
+    // Turns the light so that its forward axis points at `target`.
+    pub fn look_at(&mut self, target: Vector3) {
+        let direction = target - self.transform.position;
+        if direction.length_squared() > 0.0 {
+            self.transform.rotation = Quat::from_to(&Vector3::new(0.0, 0.0, -1.0), &direction);
+        }
+    }
+
+    pub fn view_matrix(&self) -> Matrix4 {
+        let position = self.transform.position;
+        let up = self.transform.rotation * Vector3::new(0.0, 1.0, 0.0);
+        Matrix4::look_at(position, position + self.forward(), up)
+    }
 }
\ No newline at end of file
@@ -0,0 +1,542 @@
+use crate::animation::{Joint, Keyframe, Skeleton};
+use crate::error::LoadError;
+use crate::material::Material;
+use crate::matrix4::Matrix4;
+use crate::mesh::{Mesh, Vertex};
+use crate::normal::Normals;
+use crate::quat::Quat;
+use crate::store::Store;
+use crate::texture::{AddressMode, Filter, Sampler, Texture};
+use crate::transform::Transform;
+use crate::vector2::Vector2;
+use crate::vector3::Vector3;
+use crate::vector4::Vector4;
+use gltf::animation::util::ReadOutputs;
+use gltf::animation::Interpolation;
+use gltf::material::AlphaMode;
+use gltf::mesh::Mode;
+use gltf::texture::{MinFilter, WrappingMode};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub struct Node {
+    pub name: Option<String>,
+    pub parent: Option<usize>,
+    pub children: Vec<usize>,
+    pub transform: Transform,
+    pub mesh: Option<usize>,
+    pub skin: Option<usize>,
+}
+
+#[derive(Clone)]
+pub struct Primitive {
+    pub mesh: Mesh,
+    pub material: Option<usize>,
+}
+
+// Content of a .gltf or .glb file. Skinned meshes index the joints of
+// `skins[node.skin]` and already sit in world space once skinned, so their
+// node transform must not be applied again. Only the first animation is
+// imported and it drives the skins, rigid node animation is ignored.
+pub struct Asset {
+    pub nodes: Vec<Node>,
+    pub roots: Vec<usize>,
+    pub meshes: Vec<Vec<Primitive>>,
+    pub materials: Vec<Material>,
+    pub skins: Vec<Skeleton>,
+}
+
+impl Asset {
+    pub fn load(path: &str, store: &mut Store) -> Result<Asset, LoadError> {
+        let bytes = fs::read(path).map_err(|e| LoadError::io(path, e))?;
+        Asset::parse(&bytes, path, store)
+    }
+
+    // Accepts both the JSON and the binary format. External buffers and
+    // images are resolved relative to `path`.
+    pub fn parse(bytes: &[u8], path: &str, store: &mut Store) -> Result<Asset, LoadError> {
+        let gltf::Gltf { document, blob } = gltf::Gltf::from_slice(bytes).map_err(|e| match e {
+            gltf::Error::Io(e) => LoadError::io(path, e),
+            e => LoadError::invalid(path, e.to_string()),
+        })?;
+        let dir = Path::new(path).parent().unwrap_or_else(|| Path::new("")).to_path_buf();
+        let buffers = document
+            .buffers()
+            .map(|buffer| {
+                let data = match buffer.source() {
+                    gltf::buffer::Source::Bin => blob
+                        .clone()
+                        .ok_or_else(|| LoadError::invalid(path, "missing binary chunk"))?,
+                    gltf::buffer::Source::Uri(uri) => read_uri(uri, &dir, path)?,
+                };
+                if data.len() < buffer.length() {
+                    return Err(LoadError::invalid(
+                        path,
+                        format!("buffer {} has {} bytes, expected {}", buffer.index(), data.len(), buffer.length()),
+                    ));
+                }
+                Ok(data)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut importer = Importer {
+            path,
+            dir,
+            buffers,
+            images: HashMap::new(),
+        };
+
+        let materials = document
+            .materials()
+            .map(|material| importer.material(&material, store))
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut meshes = document
+            .meshes()
+            .map(|mesh| {
+                mesh.primitives()
+                    .filter_map(|primitive| importer.primitive(&primitive).transpose())
+                    .collect::<Result<Vec<_>, _>>()
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut nodes: Vec<Node> = document
+            .nodes()
+            .map(|node| {
+                let (t, r, s) = node.transform().decomposed();
+                Node {
+                    name: node.name().map(str::to_string),
+                    parent: None,
+                    children: node.children().map(|child| child.index()).collect(),
+                    transform: Transform::new(
+                        Vector3::new(t[0], t[1], t[2]),
+                        Quat::new(r[0], r[1], r[2], r[3]),
+                        Vector3::new(s[0], s[1], s[2]),
+                    ),
+                    mesh: node.mesh().map(|mesh| mesh.index()),
+                    skin: node.skin().map(|skin| skin.index()),
+                }
+            })
+            .collect();
+        for index in 0..nodes.len() {
+            for child in nodes[index].children.clone() {
+                nodes[child].parent = Some(index);
+            }
+        }
+        let roots = match document.default_scene().or_else(|| document.scenes().next()) {
+            Some(scene) => scene.nodes().map(|node| node.index()).collect(),
+            None => (0..nodes.len()).filter(|&node| nodes[node].parent.is_none()).collect(),
+        };
+
+        let mut tracks = vec![Tracks::default(); nodes.len()];
+        let (min_time, max_time) = match document.animations().next() {
+            Some(animation) => importer.animation(&animation, &mut tracks),
+            None => (0.0, 0.0),
+        };
+        let mut skins = Vec::new();
+        // skin of the vertex joint indices of each mesh once remapped
+        let mut mesh_skins: Vec<Option<usize>> = vec![None; meshes.len()];
+        for skin in document.skins() {
+            let (skeleton, slots) = importer.skin(&skin, &nodes, &tracks, min_time, max_time);
+            for node in nodes.iter_mut().filter(|node| node.skin == Some(skin.index())) {
+                let mesh = match node.mesh {
+                    Some(mesh) => mesh,
+                    None => continue,
+                };
+                match mesh_skins[mesh] {
+                    Some(done) if done == skin.index() => continue,
+                    // a mesh bound to several skins gets a copy per skin
+                    Some(_) => {
+                        meshes.push(meshes[mesh].clone());
+                        mesh_skins.push(Some(skin.index()));
+                        node.mesh = Some(meshes.len() - 1);
+                    }
+                    None => mesh_skins[mesh] = Some(skin.index()),
+                }
+                for primitive in meshes[node.mesh.unwrap()].iter_mut() {
+                    for vertex in primitive.mesh.vertices.iter_mut() {
+                        for joint in vertex.joints.iter_mut() {
+                            // out of range joints are dropped when skinning
+                            *joint = slots.get(*joint as usize).copied().unwrap_or(u16::MAX);
+                        }
+                    }
+                }
+            }
+            skins.push(skeleton);
+        }
+
+        Ok(Asset {
+            nodes,
+            roots,
+            meshes,
+            materials,
+            skins,
+        })
+    }
+
+    // Rest pose transform of a node relative to the scene root.
+    pub fn world_matrix(&self, node: usize) -> Matrix4 {
+        let node = &self.nodes[node];
+        let local = node.transform.to_mat4();
+        match node.parent {
+            Some(parent) => self.world_matrix(parent) * local,
+            None => local,
+        }
+    }
+}
+
+// Reads a base64 `data:` URI or a file relative to the glTF document.
+fn read_uri(uri: &str, dir: &Path, path: &str) -> Result<Vec<u8>, LoadError> {
+    match uri.strip_prefix("data:") {
+        Some(data) => {
+            let (_, encoded) = data
+                .split_once(";base64,")
+                .ok_or_else(|| LoadError::invalid(path, "only base64 data URIs are supported"))?;
+            base64::decode(encoded).map_err(|e| LoadError::invalid(path, format!("invalid base64 data: {}", e)))
+        }
+        None => {
+            let file = dir.join(uri);
+            let file = file.to_string_lossy();
+            fs::read(&*file).map_err(|e| LoadError::io(&file, e))
+        }
+    }
+}
+
+fn address_mode(mode: WrappingMode) -> AddressMode {
+    match mode {
+        WrappingMode::Repeat => AddressMode::Repeat,
+        WrappingMode::MirroredRepeat => AddressMode::MirroredRepeat,
+        WrappingMode::ClampToEdge => AddressMode::ClampToEdge,
+    }
+}
+
+// Textures are always mipmapped, the minification filter only picks how
+// texels and levels are blended.
+fn sampler(sampler: &gltf::texture::Sampler) -> Sampler {
+    let filter = match sampler.min_filter() {
+        Some(MinFilter::Nearest | MinFilter::NearestMipmapNearest) => Filter::Nearest,
+        Some(MinFilter::Linear | MinFilter::LinearMipmapNearest) => Filter::Bilinear,
+        _ => Filter::Trilinear,
+    };
+    Sampler {
+        address_u: address_mode(sampler.wrap_s()),
+        address_v: address_mode(sampler.wrap_t()),
+        filter,
+        ..Sampler::new()
+    }
+}
+
+// Animation keyframes targeting a single node.
+#[derive(Clone, Default)]
+struct Tracks {
+    translations: Vec<Keyframe<Vector3>>,
+    rotations: Vec<Keyframe<Quat>>,
+    scales: Vec<Keyframe<Vector3>>,
+}
+
+// Cubic spline samplers store an in-tangent, a value and an out-tangent per
+// key, only the values are kept and interpolated linearly like step ones.
+fn keyframes<T>(times: &[f32], values: impl Iterator<Item = T>, cubic: bool) -> Vec<Keyframe<T>> {
+    let values: Vec<T> = if cubic {
+        values.skip(1).step_by(3).collect()
+    } else {
+        values.collect()
+    };
+    times
+        .iter()
+        .zip(values)
+        .map(|(&time, value)| Keyframe { time, value })
+        .collect()
+}
+
+// Joints without a channel keep their rest pose.
+fn or_rest<T: Copy>(keys: &[Keyframe<T>], value: T) -> Vec<Keyframe<T>> {
+    if keys.is_empty() {
+        vec![Keyframe { time: 0.0, value }]
+    } else {
+        keys.to_vec()
+    }
+}
+
+struct Importer<'a> {
+    path: &'a str,
+    dir: PathBuf,
+    buffers: Vec<Vec<u8>>,
+    // store ids of the images loaded so far, by index and whether they are
+    // decoded as sRGB
+    images: HashMap<(usize, bool), u32>,
+}
+
+impl Importer<'_> {
+    fn buffer(&self, buffer: gltf::Buffer) -> Option<&[u8]> {
+        self.buffers.get(buffer.index()).map(Vec::as_slice)
+    }
+
+    fn texture(&mut self, texture: Option<gltf::Texture>, srgb: bool, store: &mut Store) -> Result<Option<u32>, LoadError> {
+        let texture = match texture {
+            Some(texture) => texture,
+            None => return Ok(None),
+        };
+        let id = self.image(texture.source(), srgb, store)?;
+        Ok(Some(store.with_sampler(id, sampler(&texture.sampler()))))
+    }
+
+    fn image(&mut self, image: gltf::Image, srgb: bool, store: &mut Store) -> Result<u32, LoadError> {
+        let key = (image.index(), srgb);
+        if let Some(&id) = self.images.get(&key) {
+            return Ok(id);
+        }
+        let id = match image.source() {
+            gltf::image::Source::View { view, .. } => {
+                let start = view.offset();
+                let bytes = self.buffers[view.buffer().index()]
+                    .get(start..start + view.length())
+                    .ok_or_else(|| LoadError::invalid(self.path, format!("image {} is out of bounds", image.index())))?;
+                store.add_texture(Texture::decode(bytes, self.path, srgb)?)
+            }
+            gltf::image::Source::Uri { uri, .. } if uri.starts_with("data:") => {
+                let bytes = read_uri(uri, &self.dir, self.path)?;
+                store.add_texture(Texture::decode(&bytes, self.path, srgb)?)
+            }
+            gltf::image::Source::Uri { uri, .. } => store.load_texture(&self.dir.join(uri).to_string_lossy(), srgb)?,
+        };
+        self.images.insert(key, id);
+        Ok(id)
+    }
+
+    fn material(&mut self, material: &gltf::Material, store: &mut Store) -> Result<Material, LoadError> {
+        let pbr = material.pbr_metallic_roughness();
+        let [r, g, b, a] = pbr.base_color_factor();
+        let [er, eg, eb] = material.emissive_factor();
+        let (enable_blend, alpha_cutoff) = match material.alpha_mode() {
+            AlphaMode::Opaque => (false, 0.0),
+            AlphaMode::Mask => (false, material.alpha_cutoff().unwrap_or(0.5)),
+            AlphaMode::Blend => (true, 0.0),
+        };
+        Ok(Material {
+            basecolor_factor: Vector4::new(r, g, b, a),
+            metallic_factor: pbr.metallic_factor(),
+            roughness_factor: pbr.roughness_factor(),
+            basecolor_map: self.texture(pbr.base_color_texture().map(|info| info.texture()), true, store)?,
+            metallic_roughness_map: self.texture(pbr.metallic_roughness_texture().map(|info| info.texture()), false, store)?,
+            emission_factor: Vector3::new(er, eg, eb),
+            emission_map: self.texture(material.emissive_texture().map(|info| info.texture()), true, store)?,
+            normal_map: self.texture(material.normal_texture().map(|info| info.texture()), false, store)?,
+            occlusion_map: self.texture(material.occlusion_texture().map(|info| info.texture()), false, store)?,
+            double_sided: material.double_sided(),
+            enable_blend,
+            alpha_cutoff,
+            ..Material::new()
+        })
+    }
+
+    // Returns `None` for point and line primitives, which are not drawn.
+    fn primitive(&self, primitive: &gltf::Primitive) -> Result<Option<Primitive>, LoadError> {
+        let reader = primitive.reader(|buffer| self.buffer(buffer));
+        let mut vertices: Vec<Vertex> = match reader.read_positions() {
+            Some(positions) => positions
+                .map(|[x, y, z]| Vertex::new(Vector3::new(x, y, z), Vector2::new(0.0, 0.0), Vector3::zero()))
+                .collect(),
+            None => return Ok(None),
+        };
+        let normals = reader.read_normals();
+        let has_normals = normals.is_some();
+        if let Some(normals) = normals {
+            for (vertex, [x, y, z]) in vertices.iter_mut().zip(normals) {
+                vertex.normal = Vector3::new(x, y, z);
+            }
+        }
+        // glTF already puts v = 0 at the top of the image like `Texture`
+        if let Some(tex_coords) = reader.read_tex_coords(0) {
+            for (vertex, [u, v]) in vertices.iter_mut().zip(tex_coords.into_f32()) {
+                vertex.tex_coord = Vector2::new(u, v);
+            }
+        }
+        if let Some(tangents) = reader.read_tangents() {
+            for (vertex, [x, y, z, w]) in vertices.iter_mut().zip(tangents) {
+                vertex.tangent = Vector4::new(x, y, z, w);
+            }
+        }
+        if let (Some(joints), Some(weights)) = (reader.read_joints(0), reader.read_weights(0)) {
+            for ((vertex, joints), weights) in vertices.iter_mut().zip(joints.into_u16()).zip(weights.into_f32()) {
+                vertex.joints = joints;
+                vertex.weights = weights;
+            }
+        }
+
+        let indices: Vec<usize> = match reader.read_indices() {
+            Some(indices) => indices.into_u32().map(|index| index as usize).collect(),
+            None => (0..vertices.len()).collect(),
+        };
+        if let Some(&index) = indices.iter().find(|&&index| index >= vertices.len()) {
+            return Err(LoadError::invalid(
+                self.path,
+                format!("vertex index {} out of range for {} vertices", index, vertices.len()),
+            ));
+        }
+        let count = indices.len();
+        let mut indices: Vec<usize> = match primitive.mode() {
+            Mode::Triangles => indices,
+            Mode::TriangleStrip => (2..count)
+                .flat_map(|i| match i % 2 {
+                    0 => [indices[i - 2], indices[i - 1], indices[i]],
+                    _ => [indices[i - 1], indices[i - 2], indices[i]],
+                })
+                .collect(),
+            Mode::TriangleFan => (2..count)
+                .flat_map(|i| [indices[0], indices[i - 1], indices[i]])
+                .collect(),
+            _ => return Ok(None),
+        };
+        indices.truncate(indices.len() / 3 * 3);
+
+        let mut mesh = Mesh {
+            vertices,
+            indices,
+            transform: Transform::identity(),
+        };
+        // the spec asks for flat normals when a primitive has none
+        if !has_normals {
+            mesh.generate_normals(Normals::Flat);
+        }
+        Ok(Some(Primitive {
+            mesh,
+            material: primitive.material().index(),
+        }))
+    }
+
+    // Reads every channel into per-node tracks and returns the time range.
+    fn animation(&self, animation: &gltf::Animation, tracks: &mut [Tracks]) -> (f32, f32) {
+        let mut min_time = f32::MAX;
+        let mut max_time = f32::MIN;
+        for channel in animation.channels() {
+            let reader = channel.reader(|buffer| self.buffer(buffer));
+            let (times, outputs) = match (reader.read_inputs(), reader.read_outputs()) {
+                (Some(inputs), Some(outputs)) => (inputs.collect::<Vec<f32>>(), outputs),
+                _ => continue,
+            };
+            let cubic = matches!(channel.sampler().interpolation(), Interpolation::CubicSpline);
+            let track = &mut tracks[channel.target().node().index()];
+            match outputs {
+                ReadOutputs::Translations(values) => {
+                    track.translations = keyframes(&times, values.map(|[x, y, z]| Vector3::new(x, y, z)), cubic)
+                }
+                ReadOutputs::Rotations(values) => {
+                    track.rotations = keyframes(&times, values.into_f32().map(|[x, y, z, w]| Quat::new(x, y, z, w)), cubic)
+                }
+                ReadOutputs::Scales(values) => {
+                    track.scales = keyframes(&times, values.map(|[x, y, z]| Vector3::new(x, y, z)), cubic)
+                }
+                ReadOutputs::MorphTargetWeights(_) => continue,
+            }
+            for &time in &times {
+                min_time = min_time.min(time);
+                max_time = max_time.max(time);
+            }
+        }
+        if min_time > max_time {
+            (0.0, 0.0)
+        } else {
+            (min_time, max_time)
+        }
+    }
+
+    // Builds a skeleton from the joints of a skin and all of their ancestors,
+    // ordered so that parents come before children. Also returns the
+    // skeleton index of every skin joint, to remap vertex joint indices.
+    fn skin(&self, skin: &gltf::Skin, nodes: &[Node], tracks: &[Tracks], min_time: f32, max_time: f32) -> (Skeleton, Vec<u16>) {
+        let joints: Vec<usize> = skin.joints().map(|joint| joint.index()).collect();
+        let mut needed = vec![false; nodes.len()];
+        for &joint in &joints {
+            let mut node = Some(joint);
+            while let Some(index) = node.filter(|&index| !needed[index]) {
+                needed[index] = true;
+                node = nodes[index].parent;
+            }
+        }
+        let mut order: Vec<usize> = Vec::new();
+        let mut stack: Vec<usize> = (0..nodes.len())
+            .rev()
+            .filter(|&index| needed[index] && nodes[index].parent.is_none())
+            .collect();
+        while let Some(index) = stack.pop() {
+            order.push(index);
+            stack.extend(nodes[index].children.iter().rev().filter(|&&child| needed[child]));
+        }
+        let mut slots = vec![usize::MAX; nodes.len()];
+        for (slot, &index) in order.iter().enumerate() {
+            slots[index] = slot;
+        }
+
+        let mut inverse_binds = vec![Matrix4::identity(); nodes.len()];
+        let reader = skin.reader(|buffer| self.buffer(buffer));
+        if let Some(matrices) = reader.read_inverse_bind_matrices() {
+            for (&joint, columns) in joints.iter().zip(matrices) {
+                inverse_binds[joint] = Matrix4::from_array(columns.concat().try_into().unwrap());
+            }
+        }
+
+        let skeleton_joints = order
+            .iter()
+            .map(|&index| {
+                let node = &nodes[index];
+                let track = &tracks[index];
+                Joint {
+                    parent: node.parent.map(|parent| slots[parent]),
+                    inverse_bind: inverse_binds[index],
+                    translations: or_rest(&track.translations, node.transform.position),
+                    rotations: or_rest(&track.rotations, node.transform.rotation),
+                    scales: or_rest(&track.scales, node.transform.scale),
+                }
+            })
+            .collect();
+        let skeleton = Skeleton {
+            joints: skeleton_joints,
+            min_time,
+            max_time,
+        };
+        (skeleton, joints.iter().map(|&joint| slots[joint] as u16).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn embedded_buffers_and_node_hierarchy() {
+        let source = r#"{
+            "asset": { "version": "2.0" },
+            "scene": 0,
+            "scenes": [{ "nodes": [0] }],
+            "nodes": [
+                { "children": [1], "scale": [2, 2, 2] },
+                { "mesh": 0, "translation": [1, 0, 0] }
+            ],
+            "meshes": [{ "primitives": [{ "attributes": { "POSITION": 0 }, "indices": 1 }] }],
+            "buffers": [{
+                "byteLength": 44,
+                "uri": "data:application/octet-stream;base64,AAAAAAAAAAAAAAAAAACAPwAAAAAAAAAAAAAAAAAAgD8AAAAAAAABAAIAAAA="
+            }],
+            "bufferViews": [
+                { "buffer": 0, "byteOffset": 0, "byteLength": 36 },
+                { "buffer": 0, "byteOffset": 36, "byteLength": 6 }
+            ],
+            "accessors": [
+                { "bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3",
+                  "min": [0, 0, 0], "max": [1, 1, 0] },
+                { "bufferView": 1, "componentType": 5123, "count": 3, "type": "SCALAR" }
+            ]
+        }"#;
+        let asset = Asset::parse(source.as_bytes(), "test.gltf", &mut Store::new()).unwrap();
+        assert_eq!(asset.roots, vec![0]);
+        assert_eq!(asset.nodes[1].parent, Some(0));
+        let primitive = &asset.meshes[0][0];
+        assert_eq!(primitive.material, None);
+        assert_eq!(primitive.mesh.indices, vec![0, 1, 2]);
+        assert_eq!(primitive.mesh.vertices[1].position, Vector3::new(1.0, 0.0, 0.0));
+        // the primitive has no NORMAL attribute, its normals are flat
+        assert!(primitive.mesh.vertices.iter().all(|vertex| vertex.normal == Vector3::new(0.0, 0.0, 1.0)));
+        let p = asset.world_matrix(1) * Vector4::new(0.0, 1.0, 0.0, 1.0);
+        assert_eq!(p, Vector4::new(2.0, 2.0, 0.0, 1.0));
+    }
+}
@@ -1,10 +1,233 @@
+use crate::error::LoadError;
+use crate::scene::MaterialDesc;
+use crate::store::Store;
 use crate::vector3::Vector3;
+use crate::vector4::Vector4;
+
+// Which way the green channel of a tangent space normal map points, the
+// red channel is always along the tangent.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum NormalFormat {
+    // green points up the image, as in glTF
+    OpenGl,
+    // green points down the image
+    DirectX,
+}
+
+// The lighting model a material was authored for, the scene shading draws
+// each material with its own.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Model {
+    MetallicRoughness,
+    BlinnPhong,
+    SpecularGlossiness,
+}
 
 pub struct Material {
-    pub basecolor_map : u32,
-    pub metallic_map : u32,
-    pub emission_map : u32,
-    pub roughness_map : u32,
-    pub normal_map : u32,
-    pub occlusion_map : u32,
-}
\ No newline at end of file
+    pub model: Model,
+    pub basecolor_factor: Vector4,
+    pub metallic_factor: f32,
+    pub roughness_factor: f32,
+    pub basecolor_map: Option<u32>,
+    pub metallic_map: Option<u32>,
+    pub emission_factor: Vector3,
+    pub emission_map: Option<u32>,
+    pub roughness_map: Option<u32>,
+    // glTF packs roughness in green and metalness in blue of a single map
+    pub metallic_roughness_map: Option<u32>,
+    // tangent space, stored without sRGB encoding
+    pub normal_map: Option<u32>,
+    pub normal_format: NormalFormat,
+    pub occlusion_map: Option<u32>,
+    pub double_sided: bool,
+    pub enable_blend: bool,
+    pub alpha_cutoff: f32,
+    // color of the Blinn-Phong highlights or reflectance at normal incidence
+    // of specular-glossiness materials, multiplied by the map
+    pub specular_factor: Vector3,
+    pub specular_map: Option<u32>,
+    pub shininess: f32,
+    // one minus the roughness, multiplied by the red channel of the map
+    pub glossiness_factor: f32,
+    pub glossiness_map: Option<u32>,
+}
+
+impl Default for Material {
+    fn default() -> Material {
+        Material::new()
+    }
+}
+
+impl Material {
+    pub fn new() -> Material {
+        Material {
+            model: Model::MetallicRoughness,
+            basecolor_factor: Vector4::new(1.0, 1.0, 1.0, 1.0),
+            metallic_factor: 1.0,
+            roughness_factor: 1.0,
+            basecolor_map: None,
+            metallic_map: None,
+            emission_factor: Vector3::zero(),
+            emission_map: None,
+            roughness_map: None,
+            metallic_roughness_map: None,
+            normal_map: None,
+            normal_format: NormalFormat::OpenGl,
+            occlusion_map: None,
+            double_sided: false,
+            enable_blend: false,
+            alpha_cutoff: 0.0,
+            specular_factor: Vector3::zero(),
+            specular_map: None,
+            shininess: 0.0,
+            glossiness_factor: 1.0,
+            glossiness_map: None,
+        }
+    }
+
+    pub fn from_desc(desc: &MaterialDesc, store: &mut Store) -> Result<Material, LoadError> {
+        // color maps are sRGB, normal, metalness, roughness, glossiness and
+        // occlusion maps hold linear data
+        let mut load = |path: &Option<String>, srgb| path.as_ref().map(|path| store.load_texture(path, srgb)).transpose();
+        // scene files have no emissive factor, the map is used as is
+        let emission = |path: &Option<String>| match path {
+            Some(_) => Vector3::new(1.0, 1.0, 1.0),
+            None => Vector3::zero(),
+        };
+        Ok(match desc {
+            MaterialDesc::PbrMetallic(desc) => Material {
+                basecolor_factor: desc.basecolor_factor,
+                metallic_factor: desc.metalness_factor,
+                roughness_factor: desc.roughness_factor,
+                basecolor_map: load(&desc.basecolor_map, true)?,
+                metallic_map: load(&desc.metalness_map, false)?,
+                emission_factor: emission(&desc.emission_map),
+                emission_map: load(&desc.emission_map, true)?,
+                roughness_map: load(&desc.roughness_map, false)?,
+                metallic_roughness_map: None,
+                normal_map: load(&desc.normal_map, false)?,
+                normal_format: NormalFormat::OpenGl,
+                occlusion_map: load(&desc.occlusion_map, false)?,
+                double_sided: desc.double_sided,
+                enable_blend: desc.enable_blend,
+                alpha_cutoff: desc.alpha_cutoff,
+                ..Material::new()
+            },
+            // the roughness approximates the highlights when drawn with the
+            // metallic-roughness shader
+            MaterialDesc::Blinn(desc) => Material {
+                model: Model::BlinnPhong,
+                basecolor_factor: desc.basecolor,
+                metallic_factor: 0.0,
+                roughness_factor: (2.0 / (desc.shininess + 2.0)).sqrt(),
+                basecolor_map: load(&desc.diffuse_map, true)?,
+                emission_factor: emission(&desc.emission_map),
+                emission_map: load(&desc.emission_map, true)?,
+                normal_map: load(&desc.normal_map, false)?,
+                specular_factor: desc.specular,
+                specular_map: load(&desc.specular_map, true)?,
+                shininess: desc.shininess,
+                double_sided: desc.double_sided,
+                enable_blend: desc.enable_blend,
+                alpha_cutoff: desc.alpha_cutoff,
+                ..Material::new()
+            },
+            // the base color is the diffuse color, metalness and roughness
+            // are left for the shaders without a specular-glossiness path
+            MaterialDesc::PbrSpecular(desc) => Material {
+                model: Model::SpecularGlossiness,
+                basecolor_factor: desc.diffuse_factor,
+                metallic_factor: 0.0,
+                roughness_factor: 1.0 - desc.glossiness_factor,
+                basecolor_map: load(&desc.diffuse_map, true)?,
+                specular_factor: desc.specular_factor,
+                specular_map: load(&desc.specular_map, true)?,
+                glossiness_factor: desc.glossiness_factor,
+                glossiness_map: load(&desc.glossiness_map, false)?,
+                emission_factor: emission(&desc.emission_map),
+                emission_map: load(&desc.emission_map, true)?,
+                normal_map: load(&desc.normal_map, false)?,
+                occlusion_map: load(&desc.occlusion_map, false)?,
+                double_sided: desc.double_sided,
+                enable_blend: desc.enable_blend,
+                alpha_cutoff: desc.alpha_cutoff,
+                ..Material::new()
+            },
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scene::{BlinnMaterialDesc, PbrmMaterialDesc, PbrsMaterialDesc};
+
+    #[test]
+    fn data_maps_are_linear() {
+        let map = Some("resource/african_head/african_head_spec.tga".to_string());
+        let desc = MaterialDesc::PbrMetallic(PbrmMaterialDesc {
+            basecolor_factor: Vector4::new(1.0, 1.0, 1.0, 1.0),
+            metalness_factor: 1.0,
+            roughness_factor: 1.0,
+            basecolor_map: map.clone(),
+            metalness_map: map.clone(),
+            roughness_map: map.clone(),
+            normal_map: None,
+            occlusion_map: map,
+            emission_map: None,
+            double_sided: false,
+            enable_blend: false,
+            alpha_cutoff: 0.0,
+        });
+        let mut store = Store::new();
+        let material = Material::from_desc(&desc, &mut store).unwrap();
+        assert!(store.get_texture(material.basecolor_map.unwrap()).srgb);
+        for map in [material.metallic_map, material.roughness_map, material.occlusion_map] {
+            assert!(!store.get_texture(map.unwrap()).srgb);
+        }
+    }
+
+    #[test]
+    fn blinn_materials_keep_their_highlights() {
+        let desc = MaterialDesc::Blinn(BlinnMaterialDesc {
+            basecolor: Vector4::new(0.5, 0.5, 0.5, 1.0),
+            specular: Vector3::new(0.2, 0.2, 0.2),
+            shininess: 32.0,
+            diffuse_map: None,
+            specular_map: None,
+            normal_map: None,
+            emission_map: None,
+            double_sided: false,
+            enable_blend: false,
+            alpha_cutoff: 0.0,
+        });
+        let material = Material::from_desc(&desc, &mut Store::new()).unwrap();
+        assert_eq!(material.model, Model::BlinnPhong);
+        assert_eq!(material.specular_factor, Vector3::new(0.2, 0.2, 0.2));
+        assert_eq!(material.shininess, 32.0);
+        assert_eq!(material.metallic_factor, 0.0);
+    }
+
+    #[test]
+    fn specular_glossiness_materials_keep_their_workflow() {
+        let desc = MaterialDesc::PbrSpecular(PbrsMaterialDesc {
+            diffuse_factor: Vector4::new(0.8, 0.8, 0.8, 1.0),
+            specular_factor: Vector3::new(0.5, 0.5, 0.5),
+            glossiness_factor: 0.75,
+            diffuse_map: None,
+            specular_map: None,
+            glossiness_map: None,
+            normal_map: None,
+            occlusion_map: None,
+            emission_map: None,
+            double_sided: false,
+            enable_blend: false,
+            alpha_cutoff: 0.0,
+        });
+        let material = Material::from_desc(&desc, &mut Store::new()).unwrap();
+        assert_eq!(material.model, Model::SpecularGlossiness);
+        assert_eq!(material.specular_factor, Vector3::new(0.5, 0.5, 0.5));
+        assert_eq!(material.glossiness_factor, 0.75);
+        assert_eq!(material.roughness_factor, 0.25);
+    }
+}
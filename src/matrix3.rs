@@ -9,6 +9,12 @@ pub struct Matrix3 {
 }
 
 
+impl Default for Matrix3 {
+    fn default() -> Matrix3 {
+        Matrix3::new()
+    }
+}
+
 impl Matrix3 {
     pub fn new() -> Matrix3 {
         Matrix3 { m: [0.0; 9] }
@@ -1,12 +1,18 @@
 use crate::vector3::Vector3;
 use crate::vector4::Vector4;
 use crate::quat::Quat;
-use std::ops::Mul;
+use std::ops::{Add, Mul};
 #[derive(Clone, Debug, Copy, PartialEq, PartialOrd)]
 pub struct Matrix4 {
     pub m: [f32; 16],
 }
 
+impl Default for Matrix4 {
+    fn default() -> Matrix4 {
+        Matrix4::new()
+    }
+}
+
 impl Matrix4 {
     pub fn new() -> Matrix4 {
         Matrix4 { m: [0.0; 16] }
@@ -23,7 +29,7 @@ impl Matrix4 {
 
 
     pub fn from_array(array: [f32; 16]) -> Matrix4 {
-        Matrix4 { m: array.clone() }
+        Matrix4 { m: array }
     }
 
     pub fn from_columns(c1: &Vector4, c2: &Vector4, c3: &Vector4, c4: &Vector4) -> Matrix4 {
@@ -93,7 +99,7 @@ impl Matrix4 {
     }
 
     pub fn look_at(eye: Vector3, target: Vector3, up: Vector3) -> Matrix4 {
-        let f = (target.clone() - eye.clone()).normalize() * -1.0f32;
+        let f = (target - eye).normalize() * -1.0f32;
         let r = (up).cross(f).normalize();
         let u = f.cross(r).normalize();
         let t = Vector3::new(-r.dot(eye), -u.dot(eye), -f.dot(eye));
@@ -173,7 +179,7 @@ impl Matrix4 {
     pub fn perspective(fov: f32, aspect: f32, near: f32, far: f32) -> Matrix4 {
         let ymax = near * f32::tan(fov * 0.5);
         let xmax = ymax * aspect;
-        return Matrix4::frustum(-xmax, xmax, -ymax, ymax, near, far);
+        Matrix4::frustum(-xmax, xmax, -ymax, ymax, near, far)
     }
 
     pub fn transpose(&self) -> Matrix4 {
@@ -187,8 +193,8 @@ impl Matrix4 {
 
     pub fn row(&self, i: usize) -> Vector4 {
         Vector4::new(
-            self.m[0 * 4 + i],
-            self.m[1 * 4 + i],
+            self.m[i],
+            self.m[4 + i],
             self.m[2 * 4 + i],
             self.m[3 * 4 + i],
         )
@@ -218,12 +224,12 @@ impl Matrix4 {
 
 
 
-    pub fn to_quat(&self) -> Quat {
+    pub fn to_quat(self) -> Quat {
         let mut up = self.up().normalize();
         let forward = self.forward().normalize();
         let right = up.cross(forward);
         up = forward.cross(right);
-        return Quat::look_dir(&forward, &up)
+        Quat::look_dir(&forward, &up)
     }
 }
 
@@ -231,7 +237,6 @@ impl Mul for Matrix4 {
     type Output = Matrix4;
 
     fn mul(self, other: Matrix4) -> Matrix4 {
-        let mut result = Matrix4::new();
         Matrix4 {
             m: [
                 self.row(0).dot(&other.col(0)),
@@ -267,6 +272,18 @@ impl Mul<Vector4> for Matrix4 {
     }
 }
 
+impl Add for Matrix4 {
+    type Output = Matrix4;
+
+    fn add(self, other: Matrix4) -> Matrix4 {
+        let mut m = self.m;
+        for (value, other) in m.iter_mut().zip(other.m.iter()) {
+            *value += other;
+        }
+        Matrix4 { m }
+    }
+}
+
 impl Mul<f32> for Matrix4 {
     type Output = Matrix4;
     fn mul(self, other: f32) -> Matrix4 {
@@ -1,10 +1,15 @@
-use crate::matrix4::Matrix4;
 use crate::vector2::Vector2;
 use crate::vector3::Vector3;
+use crate::vector4::Vector4;
 use crate::transform::Transform;
+use crate::error::LoadError;
+use crate::normal::DEFAULT_NORMALS;
 use std::{
-    fs::{self, File},
+    collections::HashMap,
+    fs::File,
     io::{BufRead, BufReader},
+    path::Path,
+    str::{FromStr, SplitWhitespace},
 };
 
 #[derive(Copy, Clone, Debug)]
@@ -12,6 +17,12 @@ pub struct Vertex {
     pub position: Vector3,
     pub tex_coord: Vector2,
     pub normal: Vector3,
+    // xyz is the tangent direction and w the handedness (+1/-1) of the
+    // bitangent, all zero when the mesh has no tangents
+    pub tangent: Vector4,
+    // skinning influences, all weights are zero for static meshes
+    pub joints: [u16; 4],
+    pub weights: [f32; 4],
 }
 
 impl Vertex {
@@ -20,104 +31,384 @@ impl Vertex {
             position,
             tex_coord,
             normal,
+            tangent: Vector4::new(0.0, 0.0, 0.0, 0.0),
+            joints: [0; 4],
+            weights: [0.0; 4],
         }
     }
 }
 
+#[derive(Clone)]
 pub struct Mesh {
     pub vertices: Vec<Vertex>,
     pub indices: Vec<usize>,
-    pub diffuse_texture: Option<String>,
     pub transform: Transform,
 }
 
+// Part of an OBJ file drawn with a single `usemtl` material.
+pub struct Submesh {
+    pub mesh: Mesh,
+    pub material: Option<String>,
+}
+
+// An OBJ file split per material, along with the `mtllib` files defining
+// those materials.
+pub struct ObjModel {
+    pub submeshes: Vec<Submesh>,
+    pub material_libraries: Vec<String>,
+}
+
+impl Default for Mesh {
+    fn default() -> Mesh {
+        Mesh::new()
+    }
+}
+
 impl Mesh {
     pub fn new() -> Mesh {
         Mesh {
             vertices: Vec::new(),
             indices: Vec::new(),
-            diffuse_texture: None,
             transform: Transform::identity(),
         }
     }
 
-    pub fn from_obj_file(path: &str) -> Mesh {
-        let mut indices: Vec<usize> = Vec::new();
-        let mut positions: Vec<Vector3> = Vec::new();
-        let mut uvs: Vec<Vector2> = Vec::new();
-        let mut normals: Vec<Vector3> = Vec::new();
-        let file = File::open(path).unwrap();
-        let reader = BufReader::new(file);
-        for line in reader.lines() {
-            let line = line.unwrap();
-            let mut parts = line.split_whitespace();
-            let first = parts.next();
-            match first {
-                Some(first) => {
-                    match first {
-                        "#" => continue,
-                        "vt" => {
-                            let x = parts.next().unwrap().parse::<f32>().unwrap();
-                            let y = parts.next().unwrap().parse::<f32>().unwrap();
-                            // println!("{:?}", Vector2::new(x, y));
-                            uvs.push(Vector2::new(x, 1.0 - y));
-                        }
-                        "vn" => {
-                            let x = parts.next().unwrap().parse::<f32>().unwrap();
-                            let y = parts.next().unwrap().parse::<f32>().unwrap();
-                            let z = parts.next().unwrap().parse::<f32>().unwrap();
-                            // println!("{:?}", Vector3::new(x, y, z));
-                            normals.push(Vector3::new(x, y, z));
+    pub fn from_obj_file(path: &str) -> Result<Mesh, LoadError> {
+        let file = File::open(path).map_err(|e| LoadError::io(path, e))?;
+        Mesh::from_obj_reader(BufReader::new(file), path)
+    }
+
+    pub fn has_tangents(&self) -> bool {
+        self.vertices.iter().any(|vertex| vertex.tangent.w != 0.0)
+    }
+
+    // `path` is only used to report errors. Materials are ignored and all
+    // faces end up in a single mesh.
+    pub fn from_obj_reader<R: BufRead>(reader: R, path: &str) -> Result<Mesh, LoadError> {
+        let data = read_obj(reader, path)?;
+        Ok(data.build(0..data.corners.len() / 3))
+    }
+}
+
+impl ObjModel {
+    pub fn load(path: &str) -> Result<ObjModel, LoadError> {
+        let file = File::open(path).map_err(|e| LoadError::io(path, e))?;
+        ObjModel::from_reader(BufReader::new(file), path)
+    }
+
+    // `mtllib` files are resolved relative to `path`.
+    pub fn from_reader<R: BufRead>(reader: R, path: &str) -> Result<ObjModel, LoadError> {
+        let data = read_obj(reader, path)?;
+        let submeshes = data
+            .materials
+            .iter()
+            .enumerate()
+            .map(|(index, material)| Submesh {
+                mesh: data.build(
+                    (0..data.triangle_materials.len()).filter(|&triangle| data.triangle_materials[triangle] == index),
+                ),
+                material: material.clone(),
+            })
+            .filter(|submesh| !submesh.mesh.indices.is_empty())
+            .collect();
+        let dir = Path::new(path).parent().unwrap_or_else(|| Path::new(""));
+        let material_libraries = data
+            .libraries
+            .iter()
+            .map(|library| dir.join(library).to_string_lossy().into_owned())
+            .collect();
+        Ok(ObjModel {
+            submeshes,
+            material_libraries,
+        })
+    }
+}
+
+// Everything read from an OBJ file before vertices are built.
+struct ObjData {
+    positions: Vec<Vector3>,
+    uvs: Vec<Vector2>,
+    normals: Vec<Vector3>,
+    tangents: Vec<Vector4>,
+    joints: Vec<[u16; 4]>,
+    weights: Vec<[f32; 4]>,
+    // three corners per triangle
+    corners: Vec<Corner>,
+    // index into `materials` of every triangle
+    triangle_materials: Vec<usize>,
+    // `usemtl` names in order of first use, `None` for faces before any
+    materials: Vec<Option<String>>,
+    libraries: Vec<String>,
+}
+
+fn read_obj<R: BufRead>(reader: R, path: &str) -> Result<ObjData, LoadError> {
+    let mut data = ObjData {
+        positions: Vec::new(),
+        uvs: Vec::new(),
+        normals: Vec::new(),
+        tangents: Vec::new(),
+        joints: Vec::new(),
+        weights: Vec::new(),
+        corners: Vec::new(),
+        triangle_materials: Vec::new(),
+        materials: vec![None],
+        libraries: Vec::new(),
+    };
+    let mut material = 0;
+    for (number, line) in reader.lines().enumerate() {
+        let line = line.map_err(|e| LoadError::io(path, e))?;
+        let at = Location { path, line: number + 1 };
+        let mut parts = line.split_whitespace();
+        let first = parts.next();
+        match first {
+            Some(first) => {
+                match first {
+                    // tangent and skinning data is stored per position as comments
+                    "#" => match parts.next() {
+                        Some("ext.tangent") => {
+                            let [x, y, z, w] = parse_values::<f32, 4>(&mut parts, &at)?;
+                            data.tangents.push(Vector4::new(x, y, z, w));
                         }
-                        "v" => {
-                            let x = parts.next().unwrap().parse::<f32>().unwrap();
-                            let y = parts.next().unwrap().parse::<f32>().unwrap();
-                            let z = parts.next().unwrap().parse::<f32>().unwrap();
-                            let vertex = Vector3::new(x, y, z);
-                            positions.push(vertex);
+                        Some("ext.joint") => data.joints.push(parse_values(&mut parts, &at)?),
+                        Some("ext.weight") => data.weights.push(parse_values(&mut parts, &at)?),
+                        _ => continue,
+                    },
+                    "vt" => {
+                        let [x, y] = parse_values::<f32, 2>(&mut parts, &at)?;
+                        data.uvs.push(Vector2::new(x, 1.0 - y));
+                    }
+                    "vn" => {
+                        let [x, y, z] = parse_values::<f32, 3>(&mut parts, &at)?;
+                        data.normals.push(Vector3::new(x, y, z));
+                    }
+                    "v" => {
+                        let [x, y, z] = parse_values::<f32, 3>(&mut parts, &at)?;
+                        data.positions.push(Vector3::new(x, y, z));
+                    }
+                    "f" => {
+                        let face = parts
+                            .map(|token| {
+                                parse_corner(token, data.positions.len(), data.uvs.len(), data.normals.len(), &at)
+                            })
+                            .collect::<Result<Vec<Corner>, LoadError>>()?;
+                        if face.len() < 3 {
+                            return Err(at.error("face with less than 3 vertices"));
                         }
-                        "f" => {
-                            let f = parts.next().unwrap();
-                            let mut first = f.split('/');
-                            let v1 = first.next().unwrap().parse::<usize>().unwrap() - 1;
-                            // let t1 = first.next().unwrap().parse::<u32>().unwrap() - 1;
-                            // let n1 = first.next().unwrap().parse::<u32>().unwrap() - 1;
-
-                            let f = parts.next().unwrap();
-                            let mut second = f.split('/');
-                            let v2 = second.next().unwrap().parse::<usize>().unwrap() - 1;
-                            // let t2 = second.next().unwrap().parse::<u32>().unwrap() - 1;
-                            // let n2 = second.next().unwrap().parse::<u32>().unwrap() - 1;
-
-                            let f = parts.next().unwrap();
-                            let mut third = f.split('/');
-                            let v3 = third.next().unwrap().parse::<usize>().unwrap() - 1;
-                            // let t3 = third.next().unwrap().parse::<u32>().unwrap() - 1;
-                            // let n3 = third.next().unwrap().parse::<u32>().unwrap() - 1;
-                            indices.push(v1);
-                            indices.push(v2);
-                            indices.push(v3);
+                        // fan triangulation, fine for the convex polygons exporters write
+                        for i in 1..face.len() - 1 {
+                            data.corners.push(face[0]);
+                            data.corners.push(face[i]);
+                            data.corners.push(face[i + 1]);
+                            data.triangle_materials.push(material);
                         }
-
-                        _ => {}
                     }
+                    "usemtl" => {
+                        let name = line.trim_start()[first.len()..].trim().to_string();
+                        material = match data.materials.iter().position(|m| m.as_deref() == Some(name.as_str())) {
+                            Some(index) => index,
+                            None => {
+                                data.materials.push(Some(name));
+                                data.materials.len() - 1
+                            }
+                        };
+                    }
+                    "mtllib" => data.libraries.extend(parts.map(str::to_string)),
+
+                    _ => {}
                 }
-                None => continue,
-            };
-        }
+            }
+            None => continue,
+        };
+    }
+    Ok(data)
+}
+
+impl ObjData {
+    // Builds a mesh out of the given triangles, every distinct (v, vt, vn)
+    // triple becomes one vertex. Normals are generated for the corners that
+    // have none.
+    fn build(&self, triangles: impl Iterator<Item = usize>) -> Mesh {
         let mut vertices: Vec<Vertex> = Vec::new();
-        for i in 0..positions.len() {
-            let position = positions[i];
-            let tex_coord = uvs[i];
-            let normal = normals[i];
-            let vertex = Vertex::new(position, tex_coord, normal);
-            vertices.push(vertex);
+        let mut indices: Vec<usize> = Vec::new();
+        let mut vertex_ids: HashMap<Corner, usize> = HashMap::new();
+        for triangle in triangles {
+            for &corner in &self.corners[triangle * 3..triangle * 3 + 3] {
+                let index = *vertex_ids.entry(corner).or_insert_with(|| {
+                    let (p, t, n) = corner;
+                    let tex_coord = t.map_or(Vector2::new(0.0, 0.0), |t| self.uvs[t]);
+                    let normal = n.map_or(Vector3::zero(), |n| self.normals[n]);
+                    let mut vertex = Vertex::new(self.positions[p], tex_coord, normal);
+                    if p < self.tangents.len() {
+                        vertex.tangent = self.tangents[p];
+                    }
+                    if p < self.joints.len() && p < self.weights.len() {
+                        vertex.joints = self.joints[p];
+                        vertex.weights = self.weights[p];
+                    }
+                    vertices.push(vertex);
+                    vertices.len() - 1
+                });
+                indices.push(index);
+            }
         }
-        Mesh {
+        let mut mesh = Mesh {
             vertices,
             indices,
-            diffuse_texture: None,
             transform: Transform::identity(),
+        };
+        let mut missing = vec![false; mesh.vertices.len()];
+        for (&(_, _, n), &index) in &vertex_ids {
+            missing[index] = n.is_none();
+        }
+        if missing.contains(&true) {
+            mesh.generate_normals_where(DEFAULT_NORMALS, |index| missing[index]);
+        }
+        mesh
+    }
+}
+
+// Line being parsed, used to report errors.
+struct Location<'a> {
+    path: &'a str,
+    line: usize,
+}
+
+impl Location<'_> {
+    fn error(&self, reason: impl Into<String>) -> LoadError {
+        LoadError::parse(self.path, self.line, reason)
+    }
+}
+
+// position, texture coordinate and normal indices of a face corner
+type Corner = (usize, Option<usize>, Option<usize>);
+
+fn parse_values<T, const N: usize>(parts: &mut SplitWhitespace, at: &Location) -> Result<[T; N], LoadError>
+where
+    T: FromStr + Default + Copy,
+{
+    let mut values = [T::default(); N];
+    for value in values.iter_mut() {
+        let token = parts
+            .next()
+            .ok_or_else(|| at.error(format!("expected {} values", N)))?;
+        *value = token
+            .parse::<T>()
+            .map_err(|_| at.error(format!("invalid number `{}`", token)))?;
+    }
+    Ok(values)
+}
+
+// Resolves a 1-based or negative (relative to the end) OBJ index.
+fn parse_index(token: &str, count: usize, at: &Location) -> Result<usize, LoadError> {
+    let index = token
+        .parse::<i64>()
+        .map_err(|_| at.error(format!("invalid index `{}`", token)))?;
+    let resolved = if index > 0 {
+        index - 1
+    } else {
+        count as i64 + index
+    };
+    if index == 0 || resolved < 0 || resolved >= count as i64 {
+        return Err(LoadError::IndexOutOfRange {
+            path: at.path.to_string(),
+            line: at.line,
+            index,
+            count,
+        });
+    }
+    Ok(resolved as usize)
+}
+
+// Parses `v`, `v/vt`, `v//vn` or `v/vt/vn`.
+fn parse_corner(token: &str, positions: usize, uvs: usize, normals: usize, at: &Location) -> Result<Corner, LoadError> {
+    let mut fields = token.split('/');
+    let position = parse_index(fields.next().unwrap_or(""), positions, at)?;
+    let tex_coord = match fields.next() {
+        Some(t) if !t.is_empty() => Some(parse_index(t, uvs, at)?),
+        _ => None,
+    };
+    let normal = match fields.next() {
+        Some(n) if !n.is_empty() => Some(parse_index(n, normals, at)?),
+        _ => None,
+    };
+    Ok((position, tex_coord, normal))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn obj_corners_are_indexed_independently() {
+        let source = "v 0 0 0
+v 1 0 0
+v 1 1 0
+v 0 1 0
+vt 0 0
+vt 1 1
+vn 0 0 1
+f 1/1/1 2/1/1 3/2/1 4/2/1
+f -4//1 -2//1 -1//1
+";
+        let mesh = Mesh::from_obj_reader(source.as_bytes(), "test.obj").unwrap();
+        assert_eq!(mesh.indices.len(), 9);
+        // the quad shares no corner with the untextured triangle
+        assert_eq!(mesh.vertices.len(), 7);
+        let v = mesh.vertices[mesh.indices[2]];
+        assert_eq!(v.position, Vector3::new(1.0, 1.0, 0.0));
+        assert_eq!(v.tex_coord, Vector2::new(1.0, 0.0));
+        assert_eq!(mesh.vertices[mesh.indices[8]].position, Vector3::new(0.0, 1.0, 0.0));
+    }
+
+    #[test]
+    fn ext_tangents_follow_their_positions() {
+        let source = "v 0 0 0
+v 1 0 0
+v 0 1 0
+vn 0 0 1
+# ext.tangent 1 0 0 1
+# ext.tangent 0 1 0 -1
+# ext.tangent 0.6 0.8 0 1
+f 3//1 1//1 2//1
+";
+        let mesh = Mesh::from_obj_reader(source.as_bytes(), "test.obj").unwrap();
+        assert!(mesh.has_tangents());
+        let tangent = |corner: usize| mesh.vertices[mesh.indices[corner]].tangent;
+        assert_eq!(tangent(0), Vector4::new(0.6, 0.8, 0.0, 1.0));
+        assert_eq!(tangent(1), Vector4::new(1.0, 0.0, 0.0, 1.0));
+        assert_eq!(tangent(2), Vector4::new(0.0, 1.0, 0.0, -1.0));
+    }
+
+    #[test]
+    fn usemtl_splits_submeshes() {
+        let source = "mtllib scene.mtl
+v 0 0 0
+v 1 0 0
+v 1 1 0
+f 1 2 3
+usemtl red
+f 1 2 3
+usemtl blue
+f 3 2 1
+usemtl red
+f 2 3 1
+";
+        let obj = ObjModel::from_reader(source.as_bytes(), "models/test.obj").unwrap();
+        assert_eq!(obj.material_libraries, vec!["models/scene.mtl"]);
+        let parts: Vec<(Option<&str>, usize)> = obj
+            .submeshes
+            .iter()
+            .map(|submesh| (submesh.material.as_deref(), submesh.mesh.indices.len()))
+            .collect();
+        assert_eq!(parts, vec![(None, 3), (Some("red"), 6), (Some("blue"), 3)]);
+    }
+
+    #[test]
+    fn obj_errors_report_line() {
+        let source = "v 0 0 0\nv 1 0 0\nf 1 2 3\n";
+        match Mesh::from_obj_reader(source.as_bytes(), "test.obj") {
+            Err(LoadError::IndexOutOfRange { line: 3, index: 3, count: 2, .. }) => {}
+            other => panic!("unexpected result {:?}", other.err()),
         }
     }
 }
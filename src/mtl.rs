@@ -0,0 +1,214 @@
+use crate::error::LoadError;
+use crate::scene::{BlinnMaterialDesc, MaterialDesc, PbrmMaterialDesc};
+use crate::vector3::Vector3;
+use crate::vector4::Vector4;
+use std::fs;
+use std::path::Path;
+
+// Values of a single `newmtl` block, missing ones keep the defaults of the
+// MTL specification.
+struct Entry {
+    name: String,
+    diffuse: Vector3,
+    specular: Vector3,
+    shininess: f32,
+    dissolve: f32,
+    illum: u32,
+    roughness: Option<f32>,
+    metallic: Option<f32>,
+    diffuse_map: Option<String>,
+    specular_map: Option<String>,
+    normal_map: Option<String>,
+    emission_map: Option<String>,
+    roughness_map: Option<String>,
+    metallic_map: Option<String>,
+}
+
+impl Entry {
+    fn new(name: &str) -> Entry {
+        Entry {
+            name: name.to_string(),
+            diffuse: Vector3::new(0.8, 0.8, 0.8),
+            specular: Vector3::zero(),
+            shininess: 0.0,
+            dissolve: 1.0,
+            illum: 2,
+            roughness: None,
+            metallic: None,
+            diffuse_map: None,
+            specular_map: None,
+            normal_map: None,
+            emission_map: None,
+            roughness_map: None,
+            metallic_map: None,
+        }
+    }
+
+    // Materials using the PBR extension become metallic-roughness ones, all
+    // others are Blinn-Phong.
+    fn into_desc(self) -> (String, MaterialDesc) {
+        let basecolor = Vector4::new(self.diffuse.x, self.diffuse.y, self.diffuse.z, self.dissolve);
+        let enable_blend = self.dissolve < 1.0;
+        let pbr = self.roughness.is_some()
+            || self.metallic.is_some()
+            || self.roughness_map.is_some()
+            || self.metallic_map.is_some();
+        let desc = if pbr {
+            MaterialDesc::PbrMetallic(PbrmMaterialDesc {
+                basecolor_factor: basecolor,
+                metalness_factor: self.metallic.unwrap_or(0.0),
+                roughness_factor: self.roughness.unwrap_or(1.0),
+                basecolor_map: self.diffuse_map,
+                metalness_map: self.metallic_map,
+                roughness_map: self.roughness_map,
+                normal_map: self.normal_map,
+                occlusion_map: None,
+                emission_map: self.emission_map,
+                double_sided: false,
+                enable_blend,
+                alpha_cutoff: 0.0,
+            })
+        } else {
+            MaterialDesc::Blinn(BlinnMaterialDesc {
+                basecolor,
+                // illumination models 0 and 1 have no highlights
+                specular: if self.illum < 2 { Vector3::zero() } else { self.specular },
+                shininess: self.shininess,
+                diffuse_map: self.diffuse_map,
+                specular_map: self.specular_map,
+                normal_map: self.normal_map,
+                emission_map: self.emission_map,
+                double_sided: false,
+                enable_blend,
+                alpha_cutoff: 0.0,
+            })
+        };
+        (self.name, desc)
+    }
+}
+
+pub fn load_mtl(path: &str) -> Result<Vec<(String, MaterialDesc)>, LoadError> {
+    let source = fs::read_to_string(path).map_err(|e| LoadError::io(path, e))?;
+    parse_mtl(&source, path)
+}
+
+// Returns the materials of an MTL file by name. Texture paths are resolved
+// relative to `path`.
+pub fn parse_mtl(source: &str, path: &str) -> Result<Vec<(String, MaterialDesc)>, LoadError> {
+    let dir = Path::new(path).parent().unwrap_or_else(|| Path::new(""));
+    let mut materials = Vec::new();
+    let mut current: Option<Entry> = None;
+    for (number, line) in source.lines().enumerate() {
+        let number = number + 1;
+        let line = line.trim();
+        let (key, value) = match line.split_once(char::is_whitespace) {
+            Some((key, value)) => (key, value.trim()),
+            None => (line, ""),
+        };
+        if key.is_empty() || key.starts_with('#') {
+            continue;
+        }
+        if key == "newmtl" {
+            materials.extend(current.take().map(Entry::into_desc));
+            current = Some(Entry::new(value));
+            continue;
+        }
+        let entry = current
+            .as_mut()
+            .ok_or_else(|| LoadError::parse(path, number, format!("`{}` before any `newmtl`", key)))?;
+        let float = || {
+            value
+                .parse::<f32>()
+                .map_err(|_| LoadError::parse(path, number, format!("invalid number `{}`", value)))
+        };
+        // options such as `-bm 0.5` come before the file name
+        let map = || {
+            value
+                .split_whitespace()
+                .last()
+                .map(|file| dir.join(file.replace('\\', "/")).to_string_lossy().into_owned())
+                .ok_or_else(|| LoadError::parse(path, number, format!("missing file name for `{}`", key)))
+        };
+        match key {
+            "Kd" => entry.diffuse = parse_color(value, path, number)?,
+            "Ks" => entry.specular = parse_color(value, path, number)?,
+            "Ns" => entry.shininess = float()?,
+            "d" => entry.dissolve = float()?,
+            "Tr" => entry.dissolve = 1.0 - float()?,
+            "illum" => {
+                entry.illum = value
+                    .parse::<u32>()
+                    .map_err(|_| LoadError::parse(path, number, format!("invalid illumination model `{}`", value)))?
+            }
+            "Pr" => entry.roughness = Some(float()?),
+            "Pm" => entry.metallic = Some(float()?),
+            "map_Kd" => entry.diffuse_map = Some(map()?),
+            "map_Ks" => entry.specular_map = Some(map()?),
+            "map_Ke" => entry.emission_map = Some(map()?),
+            "map_Bump" | "map_bump" | "bump" | "norm" => entry.normal_map = Some(map()?),
+            "map_Pr" => entry.roughness_map = Some(map()?),
+            "map_Pm" => entry.metallic_map = Some(map()?),
+            _ => {}
+        }
+    }
+    materials.extend(current.map(Entry::into_desc));
+    Ok(materials)
+}
+
+// `r g b`, or a single value used for all three channels.
+fn parse_color(value: &str, path: &str, line: usize) -> Result<Vector3, LoadError> {
+    let values = value
+        .split_whitespace()
+        .map(|token| {
+            token
+                .parse::<f32>()
+                .map_err(|_| LoadError::parse(path, line, format!("invalid number `{}`", token)))
+        })
+        .collect::<Result<Vec<f32>, LoadError>>()?;
+    match values[..] {
+        [v] => Ok(Vector3::new(v, v, v)),
+        [r, g, b] => Ok(Vector3::new(r, g, b)),
+        _ => Err(LoadError::parse(path, line, format!("invalid color `{}`", value))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn blinn_and_pbr_materials() {
+        let source = "# two materials
+newmtl shiny
+Kd 1 0.5 0
+Ks 0.5
+Ns 32
+d 0.5
+map_Kd textures\\wood.png
+map_Bump -bm 0.3 wood_normal.png
+
+newmtl metal
+Pr 0.25
+Pm 1
+";
+        let materials = parse_mtl(source, "assets/test.mtl").unwrap();
+        assert_eq!(materials.len(), 2);
+        match &materials[0] {
+            (name, MaterialDesc::Blinn(desc)) if name == "shiny" => {
+                assert_eq!(desc.basecolor, Vector4::new(1.0, 0.5, 0.0, 0.5));
+                assert_eq!(desc.specular, Vector3::new(0.5, 0.5, 0.5));
+                assert!(desc.enable_blend);
+                assert_eq!(desc.diffuse_map.as_deref(), Some("assets/textures/wood.png"));
+                assert_eq!(desc.normal_map.as_deref(), Some("assets/wood_normal.png"));
+            }
+            _ => panic!("expected a blinn material"),
+        }
+        match &materials[1] {
+            (_, MaterialDesc::PbrMetallic(desc)) => {
+                assert_eq!(desc.roughness_factor, 0.25);
+                assert_eq!(desc.metalness_factor, 1.0);
+            }
+            _ => panic!("expected a metallic-roughness material"),
+        }
+    }
+}
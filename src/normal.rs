@@ -0,0 +1,177 @@
+use crate::mesh::Mesh;
+use crate::vector3::Vector3;
+use std::collections::HashMap;
+
+// How the faces around a corner are weighted when smoothing.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Weighting {
+    // larger triangles pull harder
+    Area,
+    // by the angle of each triangle at the corner, independent of how the
+    // surface is tessellated
+    Angle,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Normals {
+    // the normal of its triangle at every corner
+    Flat,
+    // faces sharing a position are averaged, except across edges where
+    // they meet at more than `crease_angle` radians
+    Smooth { weighting: Weighting, crease_angle: f32 },
+}
+
+// Used for OBJ files without `vn`, keeps the edges of hard surface models.
+pub const DEFAULT_NORMALS: Normals = Normals::Smooth {
+    weighting: Weighting::Angle,
+    crease_angle: std::f32::consts::FRAC_PI_3,
+};
+
+fn normalize_or_zero(v: Vector3) -> Vector3 {
+    if v.length_squared() > 0.0 {
+        v.normalize()
+    } else {
+        v
+    }
+}
+
+impl Mesh {
+    // Replaces the normals of every vertex. Vertices whose corners get
+    // different normals, along creases or everywhere for flat normals, are
+    // split.
+    pub fn generate_normals(&mut self, normals: Normals) {
+        self.generate_normals_where(normals, |_| true);
+    }
+
+    // Like `generate_normals` for the vertices `selected` accepts by index,
+    // the others keep their normals.
+    pub fn generate_normals_where(&mut self, normals: Normals, selected: impl Fn(usize) -> bool) {
+        let triangles: Vec<[usize; 3]> = self.indices.chunks_exact(3).map(|t| [t[0], t[1], t[2]]).collect();
+        let positions = |t: usize| triangles[t].map(|i| self.vertices[i].position);
+        // unnormalized face normals are twice the area of their triangle
+        let areas: Vec<Vector3> = (0..triangles.len())
+            .map(|t| {
+                let [a, b, c] = positions(t);
+                (b - a).cross(c - a)
+            })
+            .collect();
+        let faces: Vec<Vector3> = areas.iter().map(|&area| normalize_or_zero(area)).collect();
+        let corner_normals: Vec<Vector3> = match normals {
+            Normals::Flat => (0..self.indices.len()).map(|corner| faces[corner / 3]).collect(),
+            Normals::Smooth { weighting, crease_angle } => {
+                let weighted: Vec<Vector3> = (0..self.indices.len())
+                    .map(|corner| {
+                        let (t, c) = (corner / 3, corner % 3);
+                        match weighting {
+                            Weighting::Area => areas[t],
+                            Weighting::Angle => {
+                                let p = positions(t);
+                                let to_next = normalize_or_zero(p[(c + 1) % 3] - p[c]);
+                                let to_previous = normalize_or_zero(p[(c + 2) % 3] - p[c]);
+                                faces[t] * to_next.dot(to_previous).clamp(-1.0, 1.0).acos()
+                            }
+                        }
+                    })
+                    .collect();
+                // corners are smoothed with every corner at the same position,
+                // whatever their texture coordinates
+                let mut shared: HashMap<[u32; 3], Vec<usize>> = HashMap::new();
+                for (corner, &index) in self.indices.iter().enumerate() {
+                    let p = self.vertices[index].position;
+                    shared.entry([p.x, p.y, p.z].map(f32::to_bits)).or_default().push(corner);
+                }
+                let min_cos = crease_angle.cos();
+                (0..self.indices.len())
+                    .map(|corner| {
+                        let p = self.vertices[self.indices[corner]].position;
+                        let face = faces[corner / 3];
+                        let sum = shared[&[p.x, p.y, p.z].map(f32::to_bits)]
+                            .iter()
+                            .filter(|&&other| faces[other / 3].dot(face) >= min_cos)
+                            .fold(Vector3::zero(), |sum, &other| sum + weighted[other]);
+                        normalize_or_zero(sum)
+                    })
+                    .collect()
+            }
+        };
+
+        let mut assigned = vec![false; self.vertices.len()];
+        let mut splits = HashMap::new();
+        for (corner, normal) in corner_normals.into_iter().enumerate() {
+            let index = self.indices[corner];
+            if !selected(index) {
+                continue;
+            }
+            if !assigned[index] {
+                assigned[index] = true;
+                self.vertices[index].normal = normal;
+            } else if self.vertices[index].normal != normal {
+                let key = (index, [normal.x, normal.y, normal.z].map(f32::to_bits));
+                let vertices = &mut self.vertices;
+                self.indices[corner] = *splits.entry(key).or_insert_with(|| {
+                    let mut vertex = vertices[index];
+                    vertex.normal = normal;
+                    vertices.push(vertex);
+                    vertices.len() - 1
+                });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mesh::Vertex;
+    use crate::vector2::Vector2;
+
+    // corner of a unit cube, three faces meeting at right angles
+    fn cube_corner() -> Mesh {
+        let mut mesh = Mesh::new();
+        for (x, y, z) in [(0.0, 0.0, 0.0), (1.0, 0.0, 0.0), (0.0, 1.0, 0.0), (0.0, 0.0, 1.0)] {
+            mesh.vertices.push(Vertex::new(Vector3::new(x, y, z), Vector2::new(0.0, 0.0), Vector3::zero()));
+        }
+        mesh.indices = vec![0, 2, 1, 0, 1, 3, 0, 3, 2];
+        mesh
+    }
+
+    #[test]
+    fn creases_and_flat_normals() {
+        let mut mesh = cube_corner();
+        mesh.generate_normals(Normals::Smooth {
+            weighting: Weighting::Angle,
+            crease_angle: std::f32::consts::PI,
+        });
+        // smoothed across all three faces at the shared corner
+        let origin = mesh.vertices[mesh.indices[0]].normal;
+        let diagonal = Vector3::new(-1.0, -1.0, -1.0).normalize();
+        assert_eq!(origin, diagonal);
+        assert_eq!(mesh.vertices.len(), 4);
+
+        // right angles are creases at the default threshold
+        let mut mesh = cube_corner();
+        mesh.generate_normals(DEFAULT_NORMALS);
+        assert_eq!(mesh.vertices[mesh.indices[0]].normal, Vector3::new(0.0, 0.0, -1.0));
+        assert_eq!(mesh.vertices[mesh.indices[3]].normal, Vector3::new(0.0, -1.0, 0.0));
+        assert_eq!(mesh.vertices.len(), 9);
+
+        let mut mesh = cube_corner();
+        mesh.generate_normals(Normals::Flat);
+        for (corner, &index) in mesh.indices.iter().enumerate() {
+            assert_eq!(mesh.vertices[index].normal, mesh.vertices[mesh.indices[corner / 3 * 3]].normal);
+        }
+
+        // loading an OBJ without normals generates them
+        let source = "v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 3\n";
+        let mesh = Mesh::from_obj_reader(source.as_bytes(), "test.obj").unwrap();
+        assert!(mesh.vertices.iter().all(|vertex| vertex.normal == Vector3::new(0.0, 0.0, 1.0)));
+
+        // authored normals survive when other corners lack them
+        let source = "v 0 0 0\nv 1 0 0\nv 0 1 0\nv 1 1 0\nvn 1 0 0\nf 1//1 2//1 3//1\nf 2 4 3\n";
+        let mesh = Mesh::from_obj_reader(source.as_bytes(), "test.obj").unwrap();
+        for (corner, &index) in mesh.indices.iter().enumerate() {
+            let expected = if corner < 3 { Vector3::new(1.0, 0.0, 0.0) } else { Vector3::new(0.0, 0.0, 1.0) };
+            assert_eq!(mesh.vertices[index].normal, expected);
+        }
+    }
+}
@@ -0,0 +1,121 @@
+use crate::material::Model;
+use crate::mesh::Vertex;
+use crate::renderer::{Fragment, Program};
+use crate::shader::{albedo, emission, opaque, sample_map, shading_normal, transform_vertex, TexCoord, Uniforms, Varyings};
+use crate::vector3::Vector3;
+use crate::vector4::Vector4;
+use std::f32::consts::PI;
+
+fn distribution_factor(n_dot_h: f32, alpha2: f32) -> f32 {
+    let n_dot_h_2 = n_dot_h * n_dot_h;
+    let factor = n_dot_h_2 * (alpha2 - 1.0) + 1.0;
+    alpha2 / (PI * factor * factor)
+}
+
+fn geom_smith_factor(dot_product: f32, roughness: f32) -> f32 {
+    let k = (roughness + 1.0) * (roughness + 1.0) / 8.0;
+    let mut denom = dot_product * (1.0 - k) + k;
+    if denom <= 0.0 {
+        denom = 0.0001;
+    }
+    1.0 / denom
+}
+
+fn fresnel(v_dot_h: f32, f0: Vector3) -> Vector3 {
+    f0 + (Vector3::new(1.0, 1.0, 1.0) - f0) * ((1.0 - v_dot_h).clamp(0.0, 1.0).powf(5.0))
+}
+
+// Split-sum image based lighting, directions are in view space.
+fn ambient_lighting(uniforms: &Uniforms, normal: Vector3, v: Vector3, diffuse: Vector3, f0: Vector3, roughness: f32) -> Vector3 {
+    let environment = match uniforms.environment {
+        Some(environment) => environment,
+        None => return Vector3::new(1.0, 1.0, 1.0),
+    };
+    let to_world = |d: Vector3| (uniforms.view_to_world * Vector4::new(d.x, d.y, d.z, 0.0)).xyz();
+    let n_dot_v = normal.dot(v).max(0.0);
+    let reflection = (-v).reflect(normal);
+    let irradiance = environment.diffuse(to_world(normal));
+    let prefiltered = environment.specular(to_world(reflection), roughness);
+    let (scale, bias) = environment.brdf(n_dot_v, roughness);
+    diffuse * irradiance + prefiltered * (f0 * scale + Vector3::new(bias, bias, bias))
+}
+
+// Diffuse color, reflectance at normal incidence and roughness of a
+// metallic-roughness or specular-glossiness material, maps included.
+pub fn pbr_surface(uniforms: &Uniforms, uv: &TexCoord, albedo: Vector3) -> (Vector3, Vector3, f32) {
+    let material = uniforms.material;
+    match material.model {
+        // as in KHR_materials_pbrSpecularGlossiness, the light reflected
+        // specularly is taken from the diffuse part
+        Model::SpecularGlossiness => {
+            let specular = material.specular_factor
+                * sample_map(uniforms, material.specular_map, uv).map_or(Vector3::new(1.0, 1.0, 1.0), |c| c.xyz());
+            let glossiness = material.glossiness_factor
+                * sample_map(uniforms, material.glossiness_map, uv).map_or(1.0, |c| c.x);
+            let diffuse = albedo * (1.0 - specular.x.max(specular.y).max(specular.z));
+            (diffuse, specular, 1.0 - glossiness)
+        }
+        Model::MetallicRoughness | Model::BlinnPhong => {
+            let mut roughness = material.roughness_factor
+                * sample_map(uniforms, material.roughness_map, uv).map_or(1.0, |c| c.x);
+            let mut metallic = material.metallic_factor
+                * sample_map(uniforms, material.metallic_map, uv).map_or(1.0, |c| c.x);
+            if let Some(c) = sample_map(uniforms, material.metallic_roughness_map, uv) {
+                roughness *= c.y;
+                metallic *= c.z;
+            }
+            let f0 = Vector3::new(0.04, 0.04, 0.04) * (1.0 - metallic) + albedo * metallic;
+            (albedo * (1.0 - metallic), f0, roughness)
+        }
+    }
+}
+
+// Metallic-roughness or specular-glossiness shading with a Cook-Torrance
+// specular term.
+pub struct PbrShader;
+
+impl Program for PbrShader {
+    type Uniforms<'a> = Uniforms<'a>;
+    type Varyings = Varyings;
+
+    fn vertex(&self, vertex: &Vertex, uniforms: &Uniforms) -> (Vector4, Varyings) {
+        transform_vertex(vertex, uniforms)
+    }
+
+    fn fragment(&self, varyings: &Fragment<Varyings>, uniforms: &Uniforms) -> Vector4 {
+        let uv = &TexCoord::new(varyings, |v| v.tex_coord);
+        let normal = shading_normal(uniforms, varyings, uv);
+        let material = uniforms.material;
+        let ao = sample_map(uniforms, material.occlusion_map, uv).map_or(1.0, |c| c.x);
+        let (diffuse, f0, roughness) = pbr_surface(uniforms, uv, albedo(uniforms, uv));
+        let v = (-varyings.position).normalize();
+
+        let mut color = ambient_lighting(uniforms, normal, v, diffuse, f0, roughness) * uniforms.light.ambient * ao
+            + emission(uniforms, uv);
+
+        let mut l = uniforms.light.transform.position - varyings.position;
+        let distance_sqr = l.length_squared();
+        l = l.normalize();
+        let n_dot_l = normal.dot(l);
+        let visibility = match uniforms.shadow_map {
+            Some(shadow_map) if n_dot_l > 0.0 => shadow_map.visibility(varyings.position),
+            _ => 1.0,
+        };
+        if n_dot_l > 0.0 && visibility > 0.0 {
+            let h = (v + l).normalize();
+            let n_dot_v = normal.dot(v).max(0.0);
+            let n_dot_h = normal.dot(h).max(0.0);
+            let v_dot_h = v.dot(h).max(0.0);
+            let attenuation = 1.0 / distance_sqr;
+            let radiance = uniforms.light.intensity * attenuation * visibility;
+            let alpha_roughness = roughness * roughness;
+            let alpha2 = alpha_roughness * alpha_roughness;
+            let d_term = distribution_factor(n_dot_h, alpha2);
+            let v_term: f32 = geom_smith_factor(n_dot_l, roughness) * geom_smith_factor(n_dot_v, roughness);
+            let f_term = fresnel(v_dot_h, f0);
+            let specular = f_term * v_term * d_term * 0.25;
+            color = color + (diffuse * (1.0 / PI) + specular) * n_dot_l * radiance;
+        }
+        opaque(color)
+    }
+}
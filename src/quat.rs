@@ -55,7 +55,7 @@ impl Quat {
         }
         let half = (f + t).normalize();
         let axis = f.cross(half);
-        return Quat::new(axis.x, axis.y, axis.z, f.dot(half));
+        Quat::new(axis.x, axis.y, axis.z, f.dot(half))
     }
 
     pub fn look_dir(dir: &Vector3, up: &Vector3) -> Quat {
@@ -68,7 +68,7 @@ impl Quat {
         let object_up = f2d * Vector3::new(0.0, 1.0, 0.0);
         let u2u = Quat::from_to(&object_up, &up);
         let result = f2d * u2u;
-        return result.normalize();
+        result.normalize()
     }
 
     pub fn normalize(&self) -> Quat {
@@ -89,6 +89,28 @@ impl Quat {
         self.x * self.x + self.y * self.y + self.z * self.z + self.w * self.w
     }
 
+    pub fn dot(&self, other: Quat) -> f32 {
+        self.x * other.x + self.y * other.y + self.z * other.z + self.w * other.w
+    }
+
+    pub fn slerp(&self, other: Quat, t: f32) -> Quat {
+        // take the shortest arc between the two orientations
+        let mut cos_theta = self.dot(other);
+        let mut other = other;
+        if cos_theta < 0.0 {
+            cos_theta = -cos_theta;
+            other = -other;
+        }
+        if cos_theta > 1.0 - K_EPSILON {
+            return (*self * (1.0 - t) + other * t).normalize();
+        }
+        let theta = cos_theta.acos();
+        let sin_theta = theta.sin();
+        let a = ((1.0 - t) * theta).sin() / sin_theta;
+        let b = (t * theta).sin() / sin_theta;
+        *self * a + other * b
+    }
+
     pub fn get_axis(&self) -> Vector3 {
        Vector3::new(self.x, self.y, self.z).normalize()
     }
@@ -97,7 +119,7 @@ impl Quat {
         2.0 * self.w.acos()
     }
 
-    pub fn to_mat4(&self) -> Matrix4 {
+    pub fn to_mat4(self) -> Matrix4 {
         let r = self.mul(Vector3::new(1.0, 0.0, 0.0));
         let u = self.mul(Vector3::new(0.0, 1.0, 0.0));
         let f = self.mul(Vector3::new(0.0, 0.0, 1.0));
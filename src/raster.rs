@@ -1,6 +1,7 @@
 use crate::framebuffer::FrameBuffer;
 use crate::vector2::Vector2;
 use crate::vector3::Vector3;
+use crate::vector4::Vector4;
 pub fn draw_line(
     x0: f32,
     y0: f32,
@@ -31,12 +32,7 @@ pub fn draw_line(
     }
     let dx = x1 - x0;
     let dy = y1 - y0;
-    let mut gradient = 0.0f32;
-    if dx == 0.0 {
-        gradient = 1.0;
-    } else {
-        gradient = dy / dx;
-    }
+    let gradient = if dx == 0.0 { 1.0 } else { dy / dx };
     let mut x_end = round(x0);
     let mut y_end = y0 + gradient * (x_end as f32 - x0);
     let mut x_gap = rfpart(x0 + 0.5);
@@ -44,14 +40,14 @@ pub fn draw_line(
     let y_pxl1 = ipart(y_end);
     if steep {
         let cur_color = color * x_gap * rfpart(y_end);
-        framebuffer.set_color(y_pxl1, x_pxl1, cur_color.to_u32());
+        framebuffer.set_color(y_pxl1, x_pxl1, Vector4::from_vector3(cur_color));
         let cur_color = color * x_gap * fpart(y_end);
-        framebuffer.set_color(y_pxl1 + 1, x_pxl1, cur_color.to_u32());
+        framebuffer.set_color(y_pxl1 + 1, x_pxl1, Vector4::from_vector3(cur_color));
     } else {
         let cur_color = color * x_gap * rfpart(y_end);
-        framebuffer.set_color(x_pxl1, y_pxl1, cur_color.to_u32());
+        framebuffer.set_color(x_pxl1, y_pxl1, Vector4::from_vector3(cur_color));
         let cur_color = color * x_gap * fpart(y_end);
-        framebuffer.set_color(x_pxl1, y_pxl1 + 1, cur_color.to_u32());
+        framebuffer.set_color(x_pxl1, y_pxl1 + 1, Vector4::from_vector3(cur_color));
     }
 
     let mut intery = y_end + gradient;
@@ -62,37 +58,37 @@ pub fn draw_line(
     let y_pxl2 = ipart(y_end);
     if steep {
         let cur_color = color * x_gap * rfpart(y_end);
-        framebuffer.set_color(y_pxl2, x_pxl2, cur_color.to_u32());
+        framebuffer.set_color(y_pxl2, x_pxl2, Vector4::from_vector3(cur_color));
         let cur_color = color * x_gap * fpart(y_end);
-        framebuffer.set_color(y_pxl2 + 1, x_pxl2, cur_color.to_u32());
+        framebuffer.set_color(y_pxl2 + 1, x_pxl2, Vector4::from_vector3(cur_color));
     } else {
         let cur_color = color * x_gap * rfpart(y_end);
-        framebuffer.set_color(x_pxl2, y_pxl2, cur_color.to_u32());
+        framebuffer.set_color(x_pxl2, y_pxl2, Vector4::from_vector3(cur_color));
         let cur_color = color * x_gap * fpart(y_end);
-        framebuffer.set_color(x_pxl2, y_pxl2 + 1, cur_color.to_u32());
+        framebuffer.set_color(x_pxl2, y_pxl2 + 1, Vector4::from_vector3(cur_color));
     }
 
     if steep {
         for x in (x_pxl1 + 1)..x_pxl2 {
             let cur_color = color * rfpart(intery);
-            framebuffer.set_color(ipart(intery), x, cur_color.to_u32());
+            framebuffer.set_color(ipart(intery), x, Vector4::from_vector3(cur_color));
             let cur_color = color * fpart(intery);
-            framebuffer.set_color(ipart(intery) + 1, x, cur_color.to_u32());
+            framebuffer.set_color(ipart(intery) + 1, x, Vector4::from_vector3(cur_color));
 
             intery += gradient;
         }
     } else {
         for x in (x_pxl1 + 1)..x_pxl2 {
             let cur_color = color * rfpart(intery);
-            framebuffer.set_color(x, ipart(intery), cur_color.to_u32());
+            framebuffer.set_color(x, ipart(intery), Vector4::from_vector3(cur_color));
             let cur_color = color * fpart(intery);
-            framebuffer.set_color(x, ipart(intery) + 1, cur_color.to_u32());
+            framebuffer.set_color(x, ipart(intery) + 1, Vector4::from_vector3(cur_color));
             intery += gradient;
         }
     }
 }
 
 // http://www.sunshine2k.de/coding/java/TriangleRasterization/TriangleRasterization.html
-pub fn draw_filled_triangle(vertices: Vec<Vector2>, color: Vector3, framebuffer: &mut FrameBuffer) {
+pub fn draw_filled_triangle(_vertices: Vec<Vector2>, _color: Vector3, _framebuffer: &mut FrameBuffer) {
     
 }
@@ -1,174 +1,742 @@
-// use crate::camera::Camera;
-// use crate::canvas::Canvas;
-// use crate::mesh::Mesh;
-// use crate::texture::Texture;
-// use crate::vector2::Vector2;
-// use crate::vector3::Vector3;
-// use crate::vector4::Vector4;
-// fn clipping(clipping_coords: &Vec<Vector4>) -> bool {
-//     let mut count = 0;
-//     for i in 0..clipping_coords.len() {
-//         let vertex = clipping_coords[i];
-//         let inside = (-vertex.w <= vertex.x && vertex.x <= vertex.w)
-//             && (-vertex.w <= vertex.y && vertex.y <= vertex.w)
-//             && (0.0 <= vertex.z && vertex.z <= vertex.w);
-//         if !inside {
-//             count += 1;
-//         }
-//     }
-//     return count == 3;
-// }
-
-// fn perspective_divide(vertex: &mut Vector4) {
-//     vertex.x /= vertex.w;
-//     vertex.y /= vertex.w;
-//     vertex.z /= vertex.w;
-// }
-
-// fn is_back_face(vertex: &Vec<Vector4>) -> bool {
-//     let xa = vertex[0].x;
-//     let xb = vertex[1].x;
-//     let xc = vertex[2].x;
-
-//     let ya = vertex[0].y;
-//     let yb = vertex[1].y;
-//     let yc = vertex[2].y;
-
-//     return xa * (yb - yc) + xb * (yc - ya) + xc * (ya - yb) <= 0.0;
-// }
-
-// fn viewport_transform(vertex: &mut Vector4, width: f64, height: f64) {
-//     vertex.x = vertex.x * (width / 2.0f64) + width / 2.0f64;
-//     vertex.y = height / 2.0f64 - vertex.y * (height / 2.0f64);
-// }
-
-// fn barycentric(vertex: &Vec<Vector4>, x: f64, y: f64) -> Vector3 {
-//     let mut v0 = Vector2::new(vertex[1].x - vertex[0].x, vertex[1].y - vertex[0].y);
-//     let mut v1 = Vector2::new(vertex[2].x - vertex[0].x, vertex[2].y - vertex[0].y);
-//     let mut v2 = Vector2::new(x - vertex[0].x, y - vertex[0].y);
-//     let d00 = v0.dot(&v0);
-//     let d01 = v0.dot(&v1);
-//     let d11 = v1.dot(&v1);
-//     let d20 = v2.dot(&v0);
-//     let d21 = v2.dot(&v1);
-
-//     let denom = d00 * d11 - d01 * d01;
-//     let v = (d11 * d20 - d01 * d21) / denom;
-//     let w = (d00 * d21 - d01 * d20) / denom;
-//     let u = 1.0 - v - w;
-//     return Vector3::new(u, v, w);
-// }
-
-// pub fn render(mesh: &Mesh, camera: &Camera, canvas: &mut Canvas) {
-//     let red = Vector3::new(1.0, 0.0, 0.0);
-//     let green = Vector3::new(0.0, 1.0, 0.0);
-//     let blue = Vector3::new(0.0, 0.0, 1.0);
-//     let view_matrix = camera.get_view_matrix();
-//     let projection_matrix = camera.get_projection_matrix();
-//     let view_projection_matrix = projection_matrix * view_matrix;
-//     let mut primitive: Vec<Vector4> = vec![Vector4::new(0.0, 0.0, 0.0, 0.0); 3];
-//     let mut uvs: Vec<Vector2> = vec![Vector2::new(0.0, 0.0); 3];
-//     let mut normals: Vec<Vector3> = vec![Vector3::new(0.0, 0.0, 0.0); 3];
-//     let mut diffuse_texture: Option<Texture> = None;
-//     match mesh.diffuse_texture {
-//         Some(ref texture) => {
-//             diffuse_texture = Some(Texture::load(texture));
-//         }
-//         None => {}
-//     }
-//     for i in 0..mesh.indices.len() / 3 {
-//         for j in 0..3 {
-//             let index = mesh.indices[i * 3 + j] as usize;
-//             let position = mesh.vertices[index];
-//             uvs[j] = mesh.uvs[index];
-//             primitive[j] =
-//                 view_projection_matrix * Vector4::new(position.x, position.y, position.z, 1.0);
-//         }
-//         if !clipping(&primitive) {
-//             // perspective divide
-//             for j in 0..3 {
-//                 perspective_divide(&mut primitive[j]);
-//             }
-//             // face culling
-//             if !is_back_face(&primitive) {
-//                 for j in 0..3 {
-//                     viewport_transform(
-//                         &mut primitive[j],
-//                         canvas.width as f64,
-//                         canvas.height as f64,
-//                     );
-//                 }
-
-//                 let mut x_max = primitive[0]
-//                     .x
-//                     .max(primitive[1].x)
-//                     .max(primitive[2].x)
-//                     .min(canvas.width as f64 - 1.0);
-//                 let mut x_min = primitive[0]
-//                     .x
-//                     .min(primitive[1].x)
-//                     .min(primitive[2].x)
-//                     .max(0.0);
-//                 let mut y_max = primitive[0]
-//                     .y
-//                     .max(primitive[1].y)
-//                     .max(primitive[2].y)
-//                     .min(canvas.height as f64 - 1.0);
-//                 let mut y_min = primitive[0]
-//                     .y
-//                     .min(primitive[1].y)
-//                     .min(primitive[2].y)
-//                     .max(0.0);
-//                 for x in x_min as usize..x_max as usize {
-//                     for y in y_min as usize..y_max as usize {
-//                         let mut barycentric =
-//                             barycentric(&primitive, x as f64 + 0.5, y as f64 + 0.5);
-//                         if barycentric.x < 0.0 || barycentric.y < 0.0 || barycentric.z < 0.0 {
-//                             continue;
-//                         }
-
-//                         let z = barycentric.x * primitive[0].z
-//                             + barycentric.y * primitive[1].z
-//                             + barycentric.z * primitive[2].z;
-//                         if canvas.get_depth(x as u32, y as u32) >= z {
-//                             barycentric = Vector3::new(
-//                                 barycentric.x / primitive[0].w,
-//                                 barycentric.y / primitive[1].w,
-//                                 barycentric.z / primitive[2].w,
-//                             );
-
-//                             barycentric = barycentric
-//                                 * (1.0 / (barycentric.x + barycentric.y + barycentric.z));
-
-//                             println!("{:?}", barycentric);
-
-//                             let uv = uvs[0] * barycentric.x
-//                                 + uvs[1] * barycentric.y
-//                                 + uvs[2] * barycentric.z;
-//                             println!("{:?}", uvs);
-//                             println!("{:?}", uv);
-//                             let mut color = Vector4::new(1.0, 0.0, 0.0, 1.0);
-
-//                             if diffuse_texture.is_some() {
-//                                 let texture = diffuse_texture.as_ref().unwrap();
-//                                 println!("{:?}", texture.width);
-//                                 let u = (uv.x * (texture.width - 1) as f64).floor() as u32;
-//                                 let v = ((1.0 - uv.y) * (texture.height - 1) as f64).floor() as u32;
-//                                 println!("{} {}", u, v);
-//                                 color = texture.get_pixel(u, v);
-//                             } else {
-//                                 let inter_color = red * barycentric.x
-//                                     + green * barycentric.y
-//                                     + blue * barycentric.z;
-//                                 color =
-//                                     Vector4::new(inter_color.x, inter_color.y, inter_color.z, 1.0);
-//                             }
-//                             canvas.set_pixel(x as u32, y as u32, color);
-//                             canvas.set_depth(x as u32, y as u32, z);
-//                         }
-//                     }
-//                 }
-//             }
-//         }
-//     }
-// }
+use crate::clip::{clip_polygon, ClipPlane};
+use crate::framebuffer::FrameBuffer;
+use crate::mesh::{Mesh, Vertex};
+use crate::vector2::Vector2;
+use crate::vector3::Vector3;
+use crate::vector4::Vector4;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+// Values a program passes from its vertex to its fragment stage. The
+// rasterizer blends them with weighted sums, so add and scale are enough.
+pub trait Interpolate: Copy {
+    fn add(&self, other: &Self) -> Self;
+    fn scale(&self, factor: f32) -> Self;
+
+    fn lerp(&self, other: &Self, t: f32) -> Self {
+        self.scale(1.0 - t).add(&other.scale(t))
+    }
+}
+
+impl Interpolate for () {
+    fn add(&self, _: &()) {}
+    fn scale(&self, _: f32) {}
+}
+
+impl Interpolate for f32 {
+    fn add(&self, other: &f32) -> f32 {
+        self + other
+    }
+
+    fn scale(&self, factor: f32) -> f32 {
+        self * factor
+    }
+}
+
+impl Interpolate for Vector2 {
+    fn add(&self, other: &Vector2) -> Vector2 {
+        *self + *other
+    }
+
+    fn scale(&self, factor: f32) -> Vector2 {
+        *self * factor
+    }
+}
+
+impl Interpolate for Vector3 {
+    fn add(&self, other: &Vector3) -> Vector3 {
+        *self + *other
+    }
+
+    fn scale(&self, factor: f32) -> Vector3 {
+        *self * factor
+    }
+}
+
+impl Interpolate for Vector4 {
+    fn add(&self, other: &Vector4) -> Vector4 {
+        *self + *other
+    }
+
+    fn scale(&self, factor: f32) -> Vector4 {
+        *self * factor
+    }
+}
+
+// Varyings of a fragment, shaded together with the rest of its 2x2 quad
+// so that their screen space derivatives are known. Dereferences to the
+// fragment's own varyings.
+pub struct Fragment<'a, V> {
+    // lanes in the order top left, top right, bottom left, bottom right
+    quad: &'a [V; 4],
+    lane: usize,
+}
+
+impl<V: Interpolate> Fragment<'_, V> {
+    // Change of a value derived from the varyings from one pixel to the
+    // next along x, the same for the whole quad.
+    pub fn ddx<T: Interpolate>(&self, value: impl Fn(&V) -> T) -> T {
+        let row = self.lane & 2;
+        value(&self.quad[row + 1]).add(&value(&self.quad[row]).scale(-1.0))
+    }
+
+    // Change along y, downwards on the screen.
+    pub fn ddy<T: Interpolate>(&self, value: impl Fn(&V) -> T) -> T {
+        let column = self.lane & 1;
+        value(&self.quad[column + 2]).add(&value(&self.quad[column]).scale(-1.0))
+    }
+}
+
+impl<V> std::ops::Deref for Fragment<'_, V> {
+    type Target = V;
+
+    fn deref(&self) -> &V {
+        &self.quad[self.lane]
+    }
+}
+
+// Vertex and fragment stages run by the renderer. Uniforms are shared by
+// a whole draw call, varyings are output per vertex and interpolated with
+// perspective correction for every fragment.
+pub trait Program: Sync {
+    type Uniforms<'a>: Sync;
+    type Varyings: Interpolate + Send + Sync;
+
+    // Returns the clip space position of the vertex.
+    fn vertex(&self, vertex: &Vertex, uniforms: &Self::Uniforms<'_>) -> (Vector4, Self::Varyings);
+
+    fn fragment(&self, fragment: &Fragment<Self::Varyings>, uniforms: &Self::Uniforms<'_>) -> Vector4;
+}
+
+// Side of the square screen tiles rasterized independently of each other.
+const TILE_SIZE: u32 = 64;
+
+// Screen positions are snapped to 1/256 of a pixel before rasterization.
+const SUBPIXEL_BITS: u32 = 8;
+const SUBPIXEL: i64 = 1 << SUBPIXEL_BITS;
+// Polygons reaching this far outside of the viewport, in NDC units, are
+// clipped to it so that the fixed point edge functions cannot overflow.
+const GUARD_BAND: f32 = 16.0;
+
+// Edge from `a` to `b` in fixed point, positive on the side of the
+// triangle's interior.
+#[derive(Copy, Clone)]
+struct Edge {
+    a: (i64, i64),
+    b: (i64, i64),
+    // 0 for top and left edges which own the pixels centered on them, -1
+    // for the others so that shared edges are drawn exactly once
+    bias: i64,
+}
+
+impl Edge {
+    // Screen y points down and triangles are wound so that the interior is
+    // on the positive side: left edges go up, top edges go right.
+    fn new(a: (i64, i64), b: (i64, i64)) -> Edge {
+        let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+        let top_left = dy < 0 || (dy == 0 && dx > 0);
+        Edge {
+            a,
+            b,
+            bias: if top_left { 0 } else { -1 },
+        }
+    }
+
+    fn evaluate(&self, x: i64, y: i64) -> i64 {
+        (self.b.0 - self.a.0) * (y - self.a.1) - (self.b.1 - self.a.1) * (x - self.a.0)
+    }
+
+    // Change of the edge function when moving one pixel right or down.
+    fn step_x(&self) -> i64 {
+        -(self.b.1 - self.a.1) * SUBPIXEL
+    }
+
+    fn step_y(&self) -> i64 {
+        (self.b.0 - self.a.0) * SUBPIXEL
+    }
+}
+
+// Triangle after clipping and the viewport transform, ready to rasterize.
+struct ScreenTriangle<V> {
+    positions: [Vector4; 3],
+    varyings: [V; 3],
+    // edge `i` is opposite to vertex `i`, its function is that vertex's
+    // barycentric weight times `area`
+    edges: [Edge; 3],
+    area: i64,
+    // pixels covered by the bounding box, x0..x1 and y0..y1
+    x0: u32,
+    x1: u32,
+    y0: u32,
+    y1: u32,
+}
+
+impl<V: Copy> ScreenTriangle<V> {
+    // Returns `None` for triangles without area, and for back faces when
+    // they are culled.
+    fn new(
+        mut positions: [Vector4; 3],
+        mut varyings: [V; 3],
+        width: u32,
+        height: u32,
+        cull_back_faces: bool,
+    ) -> Option<ScreenTriangle<V>> {
+        let snap = |p: &Vector4| {
+            (
+                (p.x * SUBPIXEL as f32).round() as i64,
+                (p.y * SUBPIXEL as f32).round() as i64,
+            )
+        };
+        let mut fixed = [snap(&positions[0]), snap(&positions[1]), snap(&positions[2])];
+        let mut area = Edge::new(fixed[0], fixed[1]).evaluate(fixed[2].0, fixed[2].1);
+        // screen y points down, so front faces wound counterclockwise in
+        // NDC have a negative area here
+        if area == 0 || (cull_back_faces && area > 0) {
+            return None;
+        }
+        if area < 0 {
+            fixed.swap(1, 2);
+            positions.swap(1, 2);
+            varyings.swap(1, 2);
+            area = -area;
+        }
+        // first and last pixels whose centers lie in the bounding box
+        let first = |min: i64| (min - SUBPIXEL / 2 + SUBPIXEL - 1).div_euclid(SUBPIXEL).max(0) as u32;
+        let end = |max: i64, size: u32| ((max - SUBPIXEL / 2).div_euclid(SUBPIXEL) + 1).clamp(0, size as i64) as u32;
+        let xs = fixed.map(|p| p.0);
+        let ys = fixed.map(|p| p.1);
+        Some(ScreenTriangle {
+            positions,
+            varyings,
+            edges: [
+                Edge::new(fixed[1], fixed[2]),
+                Edge::new(fixed[2], fixed[0]),
+                Edge::new(fixed[0], fixed[1]),
+            ],
+            area,
+            x0: first(*xs.iter().min().unwrap()),
+            x1: end(*xs.iter().max().unwrap(), width),
+            y0: first(*ys.iter().min().unwrap()),
+            y1: end(*ys.iter().max().unwrap(), height),
+        })
+    }
+}
+
+// Copy of a region of the target, owned by the thread rasterizing it.
+struct Tile {
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+    colors: Vec<Vector4>,
+    depth: Vec<f32>,
+}
+
+impl Tile {
+    fn load(target: &FrameBuffer, x: u32, y: u32) -> Tile {
+        let width = TILE_SIZE.min(target.width() - x);
+        let height = TILE_SIZE.min(target.height() - y);
+        let mut colors = Vec::with_capacity((width * height) as usize);
+        let mut depth = Vec::with_capacity((width * height) as usize);
+        for ty in y..y + height {
+            for tx in x..x + width {
+                colors.push(target.get_color(tx, ty));
+                depth.push(target.get_depth(tx, ty));
+            }
+        }
+        Tile {
+            x,
+            y,
+            width,
+            height,
+            colors,
+            depth,
+        }
+    }
+
+    fn store(&self, target: &mut FrameBuffer) {
+        for ty in 0..self.height {
+            for tx in 0..self.width {
+                let index = (ty * self.width + tx) as usize;
+                target.set_color(self.x + tx, self.y + ty, self.colors[index]);
+                target.set_depth(self.x + tx, self.y + ty, self.depth[index]);
+            }
+        }
+    }
+
+    fn index(&self, x: u32, y: u32) -> usize {
+        ((y - self.y) * self.width + x - self.x) as usize
+    }
+}
+
+// Fixed function state of the pipeline, the viewport always covers the
+// whole target.
+pub struct Renderer {
+    pub cull_back_faces: bool,
+    pub depth_test: bool,
+    pub clip_planes: Vec<ClipPlane>,
+    // tiles are shared between this many threads, 1 rasterizes on the
+    // calling thread
+    pub threads: usize,
+}
+
+impl Default for Renderer {
+    fn default() -> Renderer {
+        Renderer::new()
+    }
+}
+
+impl Renderer {
+    pub fn new() -> Renderer {
+        Renderer {
+            cull_back_faces: true,
+            depth_test: true,
+            clip_planes: ClipPlane::DEPTH.to_vec(),
+            threads: thread::available_parallelism().map_or(1, |threads| threads.get()),
+        }
+    }
+
+    // Shades the vertices and sets up every triangle, then bins them into
+    // tiles. Each tile draws its triangles in submission order so the
+    // result does not depend on the number of threads.
+    pub fn draw<P: Program>(&self, mesh: &Mesh, program: &P, uniforms: &P::Uniforms<'_>, target: &mut FrameBuffer) {
+        let mut triangles = Vec::new();
+        for triangle in mesh.indices.chunks_exact(3) {
+            let vertices = [
+                &mesh.vertices[triangle[0]],
+                &mesh.vertices[triangle[1]],
+                &mesh.vertices[triangle[2]],
+            ];
+            self.setup_triangle(vertices, program, uniforms, target, &mut triangles);
+        }
+        if triangles.is_empty() {
+            return;
+        }
+
+        let tiles_x = target.width().div_ceil(TILE_SIZE);
+        let tiles_y = target.height().div_ceil(TILE_SIZE);
+        let mut bins = vec![Vec::new(); (tiles_x * tiles_y) as usize];
+        for (index, triangle) in triangles.iter().enumerate() {
+            if triangle.x0 >= triangle.x1 || triangle.y0 >= triangle.y1 {
+                continue;
+            }
+            for tile_y in triangle.y0 / TILE_SIZE..=(triangle.y1 - 1) / TILE_SIZE {
+                for tile_x in triangle.x0 / TILE_SIZE..=(triangle.x1 - 1) / TILE_SIZE {
+                    bins[(tile_y * tiles_x + tile_x) as usize].push(index);
+                }
+            }
+        }
+        let work: Vec<(u32, &[usize])> = bins
+            .iter()
+            .enumerate()
+            .filter(|(_, bin)| !bin.is_empty())
+            .map(|(index, bin)| (index as u32, bin.as_slice()))
+            .collect();
+        // every triangle can fall between pixel centers
+        if work.is_empty() {
+            return;
+        }
+
+        let draw_tile = |&(index, bin): &(u32, &[usize]), target: &FrameBuffer| {
+            let mut tile = Tile::load(target, index % tiles_x * TILE_SIZE, index / tiles_x * TILE_SIZE);
+            for &triangle in bin {
+                self.rasterize_triangle(&triangles[triangle], program, uniforms, &mut tile);
+            }
+            tile
+        };
+        let threads = self.threads.clamp(1, work.len());
+        if threads == 1 {
+            for item in &work {
+                draw_tile(item, target).store(target);
+            }
+            return;
+        }
+        let next = AtomicUsize::new(0);
+        let shared: &FrameBuffer = target;
+        let tiles: Vec<Tile> = thread::scope(|scope| {
+            let workers: Vec<_> = (0..threads)
+                .map(|_| {
+                    scope.spawn(|| {
+                        let mut tiles = Vec::new();
+                        while let Some(item) = work.get(next.fetch_add(1, Ordering::Relaxed)) {
+                            tiles.push(draw_tile(item, shared));
+                        }
+                        tiles
+                    })
+                })
+                .collect();
+            workers
+                .into_iter()
+                .flat_map(|worker| worker.join().expect("rasterizer thread panicked"))
+                .collect()
+        });
+        for tile in &tiles {
+            tile.store(target);
+        }
+    }
+
+    fn setup_triangle<P: Program>(
+        &self,
+        vertices: [&Vertex; 3],
+        program: &P,
+        uniforms: &P::Uniforms<'_>,
+        target: &FrameBuffer,
+        triangles: &mut Vec<ScreenTriangle<P::Varyings>>,
+    ) {
+        let triangle = vertices.map(|vertex| program.vertex(vertex, uniforms));
+        let mut polygon = clip_polygon(&triangle, &self.clip_planes, P::Varyings::lerp);
+        let outside_guard_band = |p: &Vector4| p.x.abs() > GUARD_BAND * p.w || p.y.abs() > GUARD_BAND * p.w;
+        if polygon.iter().any(|(position, _)| outside_guard_band(position)) {
+            polygon = clip_polygon(&polygon, &ClipPlane::SIDES, P::Varyings::lerp);
+        }
+        if polygon.is_empty() {
+            return;
+        }
+        for (position, _) in polygon.iter_mut() {
+            *position = perspective_divide(*position);
+        }
+        // NDC covers the outer edges of the border pixels, whose centers
+        // are at +0.5
+        let width = target.width() as f32;
+        let height = target.height() as f32;
+        for (position, _) in polygon.iter_mut() {
+            *position = viewport_transform(*position, width, height);
+            // depth range [-1, 1] to [0, 1]
+            position.z = position.z * 0.5 + 0.5;
+        }
+        for i in 2..polygon.len() {
+            let (p0, v0) = polygon[0];
+            let (p1, v1) = polygon[i - 1];
+            let (p2, v2) = polygon[i];
+            let (width, height) = (target.width(), target.height());
+            triangles.extend(ScreenTriangle::new([p0, p1, p2], [v0, v1, v2], width, height, self.cull_back_faces));
+        }
+    }
+
+    // Rasterizes the part of a triangle that falls inside the tile.
+    fn rasterize_triangle<P: Program>(
+        &self,
+        triangle: &ScreenTriangle<P::Varyings>,
+        program: &P,
+        uniforms: &P::Uniforms<'_>,
+        tile: &mut Tile,
+    ) {
+        let gl_positions = triangle.positions;
+        let varyings = &triangle.varyings;
+        let edges = &triangle.edges;
+        let (x_start, x_end) = (triangle.x0.max(tile.x), triangle.x1.min(tile.x + tile.width));
+        let (y_start, y_end) = (triangle.y0.max(tile.y), triangle.y1.min(tile.y + tile.height));
+        if x_start >= x_end || y_start >= y_end {
+            return;
+        }
+        // pixels are shaded in 2x2 quads aligned to even coordinates, lanes
+        // outside of the triangle still interpolate their varyings as
+        // helpers for the derivatives but are never written
+        let (quad_x, quad_y) = (x_start & !1, y_start & !1);
+        let center = |pixel: u32| pixel as i64 * SUBPIXEL + SUBPIXEL / 2;
+        let mut row = edges.map(|edge| edge.evaluate(center(quad_x), center(quad_y)));
+        let step_x = edges.map(|edge| edge.step_x());
+        let step_y = edges.map(|edge| edge.step_y());
+        let inv_area = 1.0 / triangle.area as f32;
+        let interpolate = |bary: Vector3| {
+            let w = gl_positions[0].w * bary.x + gl_positions[1].w * bary.y + gl_positions[2].w * bary.z;
+            let bary_correct = bary
+                * Vector3::new(gl_positions[0].w, gl_positions[1].w, gl_positions[2].w)
+                * (1.0 / w);
+            varyings[0]
+                .scale(bary_correct.x)
+                .add(&varyings[1].scale(bary_correct.y))
+                .add(&varyings[2].scale(bary_correct.z))
+        };
+        for y in (quad_y..y_end).step_by(2) {
+            let mut e = row;
+            for x in (quad_x..x_end).step_by(2) {
+                let lanes = [0, 1, 2, 3].map(|lane| {
+                    let (dx, dy) = ((lane & 1) as i64, (lane >> 1) as i64);
+                    let e = [0, 1, 2].map(|i| e[i] + step_x[i] * dx + step_y[i] * dy);
+                    let (x, y) = (x + dx as u32, y + dy as u32);
+                    let covered = (x_start..x_end).contains(&x)
+                        && (y_start..y_end).contains(&y)
+                        && (0..3).all(|i| e[i] + edges[i].bias >= 0);
+                    (x, y, covered, Vector3::new(e[0] as f32, e[1] as f32, e[2] as f32) * inv_area)
+                });
+                for i in 0..3 {
+                    e[i] += step_x[i] * 2;
+                }
+                // lanes that fail the depth test become helpers too
+                let mut written = [false; 4];
+                for (lane, &(x, y, covered, bary)) in lanes.iter().enumerate() {
+                    if !covered {
+                        continue;
+                    }
+                    let z = gl_positions[0].z * bary.x + gl_positions[1].z * bary.y + gl_positions[2].z * bary.z;
+                    if !(0.0..=1.0).contains(&z) {
+                        continue;
+                    }
+                    let index = tile.index(x, y);
+                    if self.depth_test {
+                        if z > tile.depth[index] {
+                            continue;
+                        }
+                        tile.depth[index] = z;
+                    }
+                    written[lane] = true;
+                }
+                if !written.contains(&true) {
+                    continue;
+                }
+                let quad = lanes.map(|(_, _, _, bary)| interpolate(bary));
+                for (lane, &(x, y, _, _)) in lanes.iter().enumerate() {
+                    if written[lane] {
+                        let frag_color = program.fragment(&Fragment { quad: &quad, lane }, uniforms);
+                        let index = tile.index(x, y);
+                        tile.colors[index] = frag_color;
+                    }
+                }
+            }
+            for i in 0..3 {
+                row[i] += step_y[i] * 2;
+            }
+        }
+    }
+}
+
+fn viewport_transform(vertex: Vector4, width: f32, height: f32) -> Vector4 {
+    let mut vertex = vertex;
+    vertex.x = vertex.x * (width / 2.0) + width / 2.0;
+    vertex.y = height / 2.0 - vertex.y * (height / 2.0);
+    vertex
+}
+
+fn perspective_divide(vertex: Vector4) -> Vector4 {
+    let mut vertex = vertex;
+    vertex.x /= vertex.w;
+    vertex.y /= vertex.w;
+    vertex.z /= vertex.w;
+    vertex.w = 1.0 / vertex.w;
+    vertex
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct White;
+
+    impl Program for White {
+        type Uniforms<'a> = ();
+        type Varyings = ();
+
+        fn vertex(&self, vertex: &Vertex, _: &()) -> (Vector4, ()) {
+            (Vector4::new(vertex.position.x, vertex.position.y, 0.0, 1.0), ())
+        }
+
+        fn fragment(&self, _: &Fragment<()>, _: &()) -> Vector4 {
+            Vector4::new(1.0, 1.0, 1.0, 1.0)
+        }
+    }
+
+    // Passes the texture coordinate through, z of the vertex is its w.
+    struct Gradient;
+
+    impl Program for Gradient {
+        type Uniforms<'a> = ();
+        type Varyings = f32;
+
+        fn vertex(&self, vertex: &Vertex, _: &()) -> (Vector4, f32) {
+            let p = vertex.position;
+            (Vector4::new(p.x * p.z, p.y * p.z, 0.0, p.z), vertex.tex_coord.x)
+        }
+
+        fn fragment(&self, value: &Fragment<f32>, _: &()) -> Vector4 {
+            Vector4::new(**value, **value, **value, 1.0)
+        }
+    }
+
+    // Writes the derivatives of the gradient, ddx scaled by `scale`.
+    struct Slope {
+        scale: f32,
+    }
+
+    impl Program for Slope {
+        type Uniforms<'a> = ();
+        type Varyings = f32;
+
+        fn vertex(&self, vertex: &Vertex, uniforms: &()) -> (Vector4, f32) {
+            Gradient.vertex(vertex, uniforms)
+        }
+
+        fn fragment(&self, value: &Fragment<f32>, _: &()) -> Vector4 {
+            Vector4::new(value.ddx(|&u| u) * self.scale, value.ddy(|&u| u), 0.0, 1.0)
+        }
+    }
+
+    // The colors as 8-bit ARGB.
+    fn colors(target: &FrameBuffer) -> Vec<u32> {
+        target.get_colors().iter().map(|color| color.to_u32()).collect()
+    }
+
+    fn quad(left_w: f32, right_w: f32) -> Mesh {
+        let mut mesh = Mesh::new();
+        for (x, y) in [(-1.0, -1.0), (1.0, -1.0), (1.0, 1.0), (-1.0, 1.0)] {
+            let (w, u) = if x < 0.0 { (left_w, 0.0) } else { (right_w, 1.0) };
+            mesh.vertices.push(Vertex::new(Vector3::new(x, y, w), Vector2::new(u, 0.0), Vector3::zero()));
+        }
+        mesh.indices = vec![0, 1, 2, 0, 2, 3];
+        mesh
+    }
+
+    #[test]
+    fn varyings_are_perspective_correct() {
+        let mut target = FrameBuffer::new(5, 3);
+        Renderer::new().draw(&quad(1.0, 3.0), &Gradient, &(), &mut target);
+        // the center of pixel 1 is 3/10 of the way across the screen
+        let t = 1.5 / 5.0;
+        let expected = (t / 3.0) / ((1.0 - t) + t / 3.0);
+        let red = target.get_color(1, 1).x;
+        assert!((red - expected).abs() < 1.0 / 255.0);
+    }
+
+    #[test]
+    fn threads_do_not_change_the_output() {
+        // overlapping triangles at pseudo random depths crossing tile borders
+        let mut mesh = Mesh::new();
+        let mut seed = 7u32;
+        let mut random = || {
+            seed = seed.wrapping_mul(1664525).wrapping_add(1013904223);
+            (seed >> 8) as f32 / (1 << 24) as f32 * 2.0 - 1.0
+        };
+        for i in 0..60 {
+            for _ in 0..3 {
+                let position = Vector3::new(random() * 1.2, random() * 1.2, 1.0 + random() * 0.5);
+                mesh.vertices.push(Vertex::new(position, Vector2::new(random(), 0.0), Vector3::zero()));
+            }
+            mesh.indices.extend([i * 3, i * 3 + 1, i * 3 + 2]);
+        }
+        let render = |threads| {
+            let mut renderer = Renderer::new();
+            renderer.cull_back_faces = false;
+            renderer.threads = threads;
+            let mut target = FrameBuffer::new(150, 90);
+            renderer.draw(&mesh, &Gradient, &(), &mut target);
+            target
+        };
+        let (single, multi) = (render(1), render(4));
+        assert_eq!(colors(&single), colors(&multi));
+        assert!(colors(&single).iter().any(|&color| color != single.get_color(0, 0).to_u32()));
+    }
+
+    #[test]
+    fn triangles_between_pixel_centers_draw_nothing() {
+        let mut mesh = Mesh::new();
+        for (x, y) in [(0.01, 0.01), (0.05, 0.01), (0.01, 0.05)] {
+            mesh.vertices.push(Vertex::new(Vector3::new(x, y, 0.0), Vector2::new(0.0, 0.0), Vector3::zero()));
+        }
+        mesh.indices = vec![0, 1, 2];
+        let mut renderer = Renderer::new();
+        renderer.threads = 4;
+        let mut target = FrameBuffer::new(4, 4);
+        target.clear(Vector4::new(0.0, 0.0, 0.0, 0.0));
+        renderer.draw(&mesh, &White, &(), &mut target);
+        assert!(colors(&target).iter().all(|&color| color == 0));
+    }
+
+    // Takes vertex positions as clip space coordinates.
+    struct ClipSpace;
+
+    impl Program for ClipSpace {
+        type Uniforms<'a> = ();
+        type Varyings = ();
+
+        fn vertex(&self, vertex: &Vertex, _: &()) -> (Vector4, ()) {
+            (Vector4::from_vector3(vertex.position), ())
+        }
+
+        fn fragment(&self, _: &Fragment<()>, _: &()) -> Vector4 {
+            Vector4::new(1.0, 1.0, 1.0, 1.0)
+        }
+    }
+
+    #[test]
+    fn back_faces_touching_a_clip_plane_are_culled() {
+        // clockwise, the first vertex on the near plane and the second
+        // behind it, so that clipping repeats the first vertex
+        let mut mesh = Mesh::new();
+        for (x, y, z) in [(-0.5, -0.5, -1.0), (-0.5, 0.5, -2.0), (0.5, -0.5, 0.0)] {
+            mesh.vertices.push(Vertex::new(Vector3::new(x, y, z), Vector2::new(0.0, 0.0), Vector3::zero()));
+        }
+        mesh.indices = vec![0, 1, 2];
+        let mut renderer = Renderer::new();
+        let mut target = FrameBuffer::new(8, 8);
+        target.clear(Vector4::new(0.0, 0.0, 0.0, 0.0));
+        renderer.draw(&mesh, &ClipSpace, &(), &mut target);
+        assert!(colors(&target).iter().all(|&color| color == 0));
+        renderer.cull_back_faces = false;
+        renderer.draw(&mesh, &ClipSpace, &(), &mut target);
+        assert!(colors(&target).iter().any(|&color| color != 0));
+    }
+
+    #[test]
+    fn viewport_follows_the_target() {
+        let mesh = quad(1.0, 1.0);
+        let mut target = FrameBuffer::new(7, 3);
+        target.clear(Vector4::new(0.0, 0.0, 0.0, 0.0));
+        Renderer::new().draw(&mesh, &White, &(), &mut target);
+        // the quad spans the whole viewport, whatever its size, down to the
+        // last column and row
+        assert_eq!(target.get_color(0, 0).to_u32(), 0xFFFFFFFF);
+        assert_eq!(target.get_color(6, 2).to_u32(), 0xFFFFFFFF);
+        assert_eq!(target.get_depth(6, 2), 0.5);
+        assert!(colors(&target).iter().all(|&color| color == 0xFFFFFFFF));
+    }
+
+    #[test]
+    fn shared_edges_are_drawn_once() {
+        // a fan whose edges run through pixel centers, each triangle drawn
+        // on its own and the coverage summed
+        let mut target = FrameBuffer::new(8, 8);
+        let to_ndc = |x: f32, y: f32| Vector3::new(x / 4.0 - 1.0, y / 4.0 - 1.0, 0.0);
+        let ring = [(0.5, 0.5), (3.5, 0.5), (6.5, 0.5), (6.5, 3.5), (6.5, 6.5), (3.5, 6.5), (0.5, 6.5), (0.5, 3.5)];
+        let mut renderer = Renderer::new();
+        renderer.cull_back_faces = false;
+        renderer.depth_test = false;
+        let mut coverage = [0; 64];
+        for i in 0..ring.len() {
+            let (a, b) = (ring[i], ring[(i + 1) % ring.len()]);
+            let mut mesh = Mesh::new();
+            for position in [to_ndc(3.5, 3.5), to_ndc(a.0, a.1), to_ndc(b.0, b.1)] {
+                mesh.vertices.push(Vertex::new(position, Vector2::new(0.0, 0.0), Vector3::zero()));
+            }
+            mesh.indices = vec![0, 1, 2];
+            target.clear(Vector4::new(0.0, 0.0, 0.0, 0.0));
+            renderer.draw(&mesh, &White, &(), &mut target);
+            for (count, color) in coverage.iter_mut().zip(colors(&target)) {
+                *count += (color == 0xFFFFFFFF) as u32;
+            }
+        }
+        // the fan covers 6x6 pixel centers, the ones on its outline belong to
+        // the top and left sides only
+        assert!(coverage.iter().all(|&count| count <= 1));
+        assert_eq!(coverage.iter().sum::<u32>(), 36);
+        for y in 1..6 {
+            for x in 1..6 {
+                assert_eq!(coverage[y * 8 + x], 1, "pixel {} {}", x, y);
+            }
+        }
+    }
+
+    #[test]
+    fn derivatives_use_helper_lanes() {
+        // half of the quad, fragments along the diagonal need the helper
+        // lanes outside of the triangle
+        let mut mesh = quad(1.0, 1.0);
+        mesh.indices.truncate(3);
+        let mut target = FrameBuffer::new(8, 6);
+        target.clear(Vector4::new(0.0, 0.0, 0.0, 0.0));
+        // u goes from 0 to 1 over 8 pixels
+        Renderer::new().draw(&mesh, &Slope { scale: 8.0 }, &(), &mut target);
+        let written: Vec<u32> = colors(&target).into_iter().filter(|&color| color != 0).collect();
+        assert!(written.len() > 10);
+        assert!(written.iter().all(|&color| color >> 16 & 0xFF >= 0xFE && color >> 8 & 0xFF == 0));
+    }
+}
@@ -0,0 +1,540 @@
+use crate::error::LoadError;
+use crate::matrix4::Matrix4;
+use crate::vector3::Vector3;
+use crate::vector4::Vector4;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SceneType {
+    Blinn,
+    PbrMetallic,
+    PbrSpecular,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Skybox {
+    Off,
+    On,
+    Blurred,
+}
+
+#[derive(Clone, Debug)]
+pub struct Lighting {
+    pub background: Vector3,
+    // directory holding the prefiltered cubemaps
+    pub environment: Option<String>,
+    pub skybox: Skybox,
+    pub shadow: bool,
+    pub ambient: f32,
+    pub punctual: f32,
+}
+
+// Used for assets that carry no lighting block, such as glTF files.
+impl Default for Lighting {
+    fn default() -> Lighting {
+        Lighting {
+            background: Vector3::zero(),
+            environment: None,
+            skybox: Skybox::Off,
+            shadow: false,
+            ambient: 1.0,
+            punctual: 1.0,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct BlinnMaterialDesc {
+    pub basecolor: Vector4,
+    pub specular: Vector3,
+    pub shininess: f32,
+    pub diffuse_map: Option<String>,
+    pub specular_map: Option<String>,
+    pub normal_map: Option<String>,
+    pub emission_map: Option<String>,
+    pub double_sided: bool,
+    pub enable_blend: bool,
+    pub alpha_cutoff: f32,
+}
+
+impl BlinnMaterialDesc {
+    // Used when a material names no `specular` color, as in the bundled
+    // scenes. About the reflectance of common dielectrics, the specular map
+    // scales it.
+    pub const DEFAULT_SPECULAR: Vector3 = Vector3::new(0.04, 0.04, 0.04);
+}
+
+#[derive(Clone, Debug)]
+pub struct PbrmMaterialDesc {
+    pub basecolor_factor: Vector4,
+    pub metalness_factor: f32,
+    pub roughness_factor: f32,
+    pub basecolor_map: Option<String>,
+    pub metalness_map: Option<String>,
+    pub roughness_map: Option<String>,
+    pub normal_map: Option<String>,
+    pub occlusion_map: Option<String>,
+    pub emission_map: Option<String>,
+    pub double_sided: bool,
+    pub enable_blend: bool,
+    pub alpha_cutoff: f32,
+}
+
+#[derive(Clone, Debug)]
+pub struct PbrsMaterialDesc {
+    pub diffuse_factor: Vector4,
+    pub specular_factor: Vector3,
+    pub glossiness_factor: f32,
+    pub diffuse_map: Option<String>,
+    pub specular_map: Option<String>,
+    pub glossiness_map: Option<String>,
+    pub normal_map: Option<String>,
+    pub occlusion_map: Option<String>,
+    pub emission_map: Option<String>,
+    pub double_sided: bool,
+    pub enable_blend: bool,
+    pub alpha_cutoff: f32,
+}
+
+#[derive(Clone, Debug)]
+pub enum MaterialDesc {
+    Blinn(BlinnMaterialDesc),
+    PbrMetallic(PbrmMaterialDesc),
+    PbrSpecular(PbrsMaterialDesc),
+}
+
+#[derive(Clone, Debug)]
+pub struct ModelDesc {
+    pub mesh: String,
+    pub skeleton: Option<String>,
+    // joint of the skeleton the model follows, -1 when it is not attached
+    pub attached: i32,
+    pub material: usize,
+    pub transform: usize,
+}
+
+#[derive(Clone, Debug)]
+pub struct Scene {
+    pub scene_type: SceneType,
+    pub lighting: Lighting,
+    pub materials: Vec<MaterialDesc>,
+    pub transforms: Vec<Matrix4>,
+    pub models: Vec<ModelDesc>,
+}
+
+// key/value pairs of a single `name:` block, with the line of each value
+struct Fields<'a> {
+    header: usize,
+    values: HashMap<&'a str, (usize, &'a str)>,
+}
+
+struct Parser<'a> {
+    // non-empty trimmed lines with their 1-based line number
+    lines: Vec<(usize, &'a str)>,
+    current: usize,
+    path: &'a str,
+    // asset paths in scene files are relative to the assets root
+    root: &'a Path,
+}
+
+impl<'a> Parser<'a> {
+    fn error(&self, line: usize, reason: impl Into<String>) -> LoadError {
+        LoadError::parse(self.path, line, reason)
+    }
+
+    fn next_line(&mut self) -> Result<(usize, &'a str), LoadError> {
+        let last = self.lines.last().map_or(0, |&(line, _)| line);
+        let line = self
+            .lines
+            .get(self.current)
+            .copied()
+            .ok_or_else(|| self.error(last, "unexpected end of scene file"))?;
+        self.current += 1;
+        Ok(line)
+    }
+
+    fn peek_line(&self) -> Option<&'a str> {
+        self.lines.get(self.current).map(|&(_, line)| line)
+    }
+
+    fn expect_header(&mut self, name: &str) -> Result<(usize, &'a str), LoadError> {
+        let (number, line) = self.next_line()?;
+        let header = line.strip_suffix(':').unwrap_or("");
+        match header.strip_prefix(name) {
+            Some(rest) => Ok((number, rest.trim())),
+            None => Err(self.error(number, format!("expected `{}:` but found `{}`", name, line))),
+        }
+    }
+
+    fn expect_count(&mut self, name: &str) -> Result<usize, LoadError> {
+        let (number, count) = self.expect_header(name)?;
+        count
+            .parse::<usize>()
+            .map_err(|_| self.error(number, format!("invalid count `{}` for `{}`", count, name)))
+    }
+
+    // Reads the `key: value` lines following the header at `header`.
+    fn read_fields(&mut self, header: usize) -> Fields<'a> {
+        let mut fields = Fields {
+            header,
+            values: HashMap::new(),
+        };
+        while let Some(&(number, line)) = self.lines.get(self.current) {
+            match line.split_once(':') {
+                Some((key, value)) if !value.trim().is_empty() => {
+                    fields.values.insert(key.trim(), (number, value.trim()));
+                    self.current += 1;
+                }
+                _ => break,
+            }
+        }
+        fields
+    }
+
+    fn field(&self, fields: &Fields<'a>, key: &str) -> Result<(usize, &'a str), LoadError> {
+        fields
+            .values
+            .get(key)
+            .copied()
+            .ok_or_else(|| self.error(fields.header, format!("missing field `{}`", key)))
+    }
+
+    fn floats(&self, fields: &Fields<'a>, key: &str, count: usize) -> Result<Vec<f32>, LoadError> {
+        let (number, value) = self.field(fields, key)?;
+        self.parse_floats(number, value, count)
+    }
+
+    fn parse_floats(&self, number: usize, value: &str, count: usize) -> Result<Vec<f32>, LoadError> {
+        let values = value
+            .split_whitespace()
+            .map(|token| token.parse::<f32>())
+            .collect::<Result<Vec<f32>, _>>()
+            .map_err(|_| self.error(number, format!("invalid number in `{}`", value)))?;
+        if values.len() != count {
+            return Err(self.error(number, format!("expected {} numbers but found `{}`", count, value)));
+        }
+        Ok(values)
+    }
+
+    fn f32(&self, fields: &Fields<'a>, key: &str) -> Result<f32, LoadError> {
+        Ok(self.floats(fields, key, 1)?[0])
+    }
+
+    fn vector3(&self, fields: &Fields<'a>, key: &str) -> Result<Vector3, LoadError> {
+        let v = self.floats(fields, key, 3)?;
+        Ok(Vector3::new(v[0], v[1], v[2]))
+    }
+
+    fn vector4(&self, fields: &Fields<'a>, key: &str) -> Result<Vector4, LoadError> {
+        let v = self.floats(fields, key, 4)?;
+        Ok(Vector4::new(v[0], v[1], v[2], v[3]))
+    }
+
+    fn switch(&self, fields: &Fields<'a>, key: &str) -> Result<bool, LoadError> {
+        match self.field(fields, key)? {
+            (_, "on") => Ok(true),
+            (_, "off") => Ok(false),
+            (number, value) => Err(self.error(number, format!("expected `on` or `off` but found `{}`", value))),
+        }
+    }
+
+    fn index(&self, fields: &Fields<'a>, key: &str) -> Result<i64, LoadError> {
+        let (number, value) = self.field(fields, key)?;
+        value
+            .parse::<i64>()
+            .map_err(|_| self.error(number, format!("invalid {} index `{}`", key, value)))
+    }
+
+    // For the fields some scene files leave out.
+    fn has(&self, fields: &Fields<'a>, key: &str) -> bool {
+        fields.values.contains_key(key)
+    }
+
+    fn path(&self, fields: &Fields<'a>, key: &str) -> Result<Option<String>, LoadError> {
+        Ok(match self.field(fields, key)? {
+            (_, "null") => None,
+            (_, value) => Some(self.root.join(value).to_string_lossy().into_owned()),
+        })
+    }
+}
+
+fn parse_lighting(parser: &mut Parser) -> Result<Lighting, LoadError> {
+    let (header, _) = parser.expect_header("lighting")?;
+    let fields = parser.read_fields(header);
+    let environment = parser.path(&fields, "environment")?;
+    let skybox = match parser.field(&fields, "skybox")? {
+        (_, "off") => Skybox::Off,
+        (_, "on") => Skybox::On,
+        (_, "blurred") => Skybox::Blurred,
+        (number, other) => return Err(parser.error(number, format!("unknown skybox mode `{}`", other))),
+    };
+    Ok(Lighting {
+        background: parser.vector3(&fields, "background")?,
+        environment,
+        skybox,
+        shadow: parser.switch(&fields, "shadow")?,
+        ambient: parser.f32(&fields, "ambient")?,
+        punctual: parser.f32(&fields, "punctual")?,
+    })
+}
+
+fn parse_material(parser: &Parser, fields: &Fields, scene_type: SceneType) -> Result<MaterialDesc, LoadError> {
+    let double_sided = parser.switch(fields, "double_sided")?;
+    let enable_blend = parser.switch(fields, "enable_blend")?;
+    let alpha_cutoff = parser.f32(fields, "alpha_cutoff")?;
+    Ok(match scene_type {
+        SceneType::Blinn => MaterialDesc::Blinn(BlinnMaterialDesc {
+            basecolor: parser.vector4(fields, "basecolor")?,
+            specular: match parser.has(fields, "specular") {
+                true => parser.vector3(fields, "specular")?,
+                false => BlinnMaterialDesc::DEFAULT_SPECULAR,
+            },
+            shininess: parser.f32(fields, "shininess")?,
+            diffuse_map: parser.path(fields, "diffuse_map")?,
+            specular_map: parser.path(fields, "specular_map")?,
+            normal_map: match parser.has(fields, "normal_map") {
+                true => parser.path(fields, "normal_map")?,
+                false => None,
+            },
+            emission_map: parser.path(fields, "emission_map")?,
+            double_sided,
+            enable_blend,
+            alpha_cutoff,
+        }),
+        SceneType::PbrMetallic => MaterialDesc::PbrMetallic(PbrmMaterialDesc {
+            basecolor_factor: parser.vector4(fields, "basecolor_factor")?,
+            metalness_factor: parser.f32(fields, "metalness_factor")?,
+            roughness_factor: parser.f32(fields, "roughness_factor")?,
+            basecolor_map: parser.path(fields, "basecolor_map")?,
+            metalness_map: parser.path(fields, "metalness_map")?,
+            roughness_map: parser.path(fields, "roughness_map")?,
+            normal_map: parser.path(fields, "normal_map")?,
+            occlusion_map: parser.path(fields, "occlusion_map")?,
+            emission_map: parser.path(fields, "emission_map")?,
+            double_sided,
+            enable_blend,
+            alpha_cutoff,
+        }),
+        SceneType::PbrSpecular => MaterialDesc::PbrSpecular(PbrsMaterialDesc {
+            diffuse_factor: parser.vector4(fields, "diffuse_factor")?,
+            specular_factor: parser.vector3(fields, "specular_factor")?,
+            glossiness_factor: parser.f32(fields, "glossiness_factor")?,
+            diffuse_map: parser.path(fields, "diffuse_map")?,
+            specular_map: parser.path(fields, "specular_map")?,
+            glossiness_map: parser.path(fields, "glossiness_map")?,
+            normal_map: parser.path(fields, "normal_map")?,
+            occlusion_map: parser.path(fields, "occlusion_map")?,
+            emission_map: parser.path(fields, "emission_map")?,
+            double_sided,
+            enable_blend,
+            alpha_cutoff,
+        }),
+    })
+}
+
+fn parse_materials(parser: &mut Parser, scene_type: SceneType) -> Result<Vec<MaterialDesc>, LoadError> {
+    let count = parser.expect_count("materials")?;
+    let mut materials = Vec::with_capacity(count);
+    for _ in 0..count {
+        let (header, _) = parser.expect_header("material")?;
+        let fields = parser.read_fields(header);
+        materials.push(parse_material(parser, &fields, scene_type)?);
+    }
+    Ok(materials)
+}
+
+fn parse_transforms(parser: &mut Parser) -> Result<Vec<Matrix4>, LoadError> {
+    let count = parser.expect_count("transforms")?;
+    let mut transforms = Vec::with_capacity(count);
+    for _ in 0..count {
+        parser.expect_header("transform")?;
+        let mut rows = [Vector4::new(0.0, 0.0, 0.0, 0.0); 4];
+        for row in rows.iter_mut() {
+            let (number, line) = parser.next_line()?;
+            let v = parser.parse_floats(number, line, 4)?;
+            *row = Vector4::new(v[0], v[1], v[2], v[3]);
+        }
+        transforms.push(Matrix4::from_rows(&rows[0], &rows[1], &rows[2], &rows[3]));
+    }
+    Ok(transforms)
+}
+
+fn parse_models(parser: &mut Parser, materials: usize, transforms: usize) -> Result<Vec<ModelDesc>, LoadError> {
+    let count = parser.expect_count("models")?;
+    let mut models = Vec::with_capacity(count);
+    for _ in 0..count {
+        let (header, _) = parser.expect_header("model")?;
+        let fields = parser.read_fields(header);
+        let index = |key: &str, count: usize| {
+            let index = parser.index(&fields, key)?;
+            if index < 0 || index as usize >= count {
+                return Err(LoadError::IndexOutOfRange {
+                    path: parser.path.to_string(),
+                    line: parser.field(&fields, key)?.0,
+                    index,
+                    count,
+                });
+            }
+            Ok(index as usize)
+        };
+        let attached = parser.index(&fields, "attached")?;
+        models.push(ModelDesc {
+            mesh: parser
+                .path(&fields, "mesh")?
+                .ok_or_else(|| parser.error(header, "model without mesh"))?,
+            skeleton: parser.path(&fields, "skeleton")?,
+            attached: attached as i32,
+            material: index("material", materials)?,
+            transform: index("transform", transforms)?,
+        });
+    }
+    Ok(models)
+}
+
+impl Scene {
+    // `path` locates the scene file, asset paths are resolved against the
+    // directory containing the scene's own directory.
+    pub fn parse(source: &str, path: &str) -> Result<Scene, LoadError> {
+        // scenes live in `<root>/<name>/<name>.scn` and reference `<name>/...`
+        let root = Path::new(path)
+            .parent()
+            .and_then(|dir| dir.parent())
+            .unwrap_or_else(|| Path::new(""));
+        let mut parser = Parser {
+            lines: source
+                .lines()
+                .enumerate()
+                .map(|(number, line)| (number + 1, line.trim()))
+                .filter(|(_, line)| !line.is_empty())
+                .collect(),
+            current: 0,
+            path,
+            root,
+        };
+        let scene_type = match parser.next_line()? {
+            (_, "type: blinn") => SceneType::Blinn,
+            (_, "type: pbrm") => SceneType::PbrMetallic,
+            (_, "type: pbrs") => SceneType::PbrSpecular,
+            (number, other) => return Err(parser.error(number, format!("unknown scene type `{}`", other))),
+        };
+        let lighting = parse_lighting(&mut parser)?;
+        let materials = parse_materials(&mut parser, scene_type)?;
+        let transforms = parse_transforms(&mut parser)?;
+        let models = parse_models(&mut parser, materials.len(), transforms.len())?;
+        if let Some(line) = parser.peek_line() {
+            let (number, _) = parser.lines[parser.current];
+            return Err(parser.error(number, format!("unexpected `{}`", line)));
+        }
+        Ok(Scene {
+            scene_type,
+            lighting,
+            materials,
+            transforms,
+            models,
+        })
+    }
+
+    pub fn load(path: &str) -> Result<Scene, LoadError> {
+        let source = fs::read_to_string(path).map_err(|e| LoadError::io(path, e))?;
+        Scene::parse(&source, path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_bundled_scenes() {
+        let mut count = 0;
+        let mut dirs = vec![Path::new("assets").to_path_buf()];
+        while let Some(dir) = dirs.pop() {
+            for entry in fs::read_dir(dir).unwrap() {
+                let path = entry.unwrap().path();
+                if path.is_dir() {
+                    dirs.push(path);
+                    continue;
+                }
+                if path.extension().is_none_or(|extension| extension != "scn") {
+                    continue;
+                }
+                let scene = Scene::load(path.to_str().unwrap()).unwrap();
+                assert!(!scene.models.is_empty(), "{}", path.display());
+                for model in &scene.models {
+                    assert!(Path::new(&model.mesh).exists(), "{}", model.mesh);
+                }
+                count += 1;
+            }
+        }
+        assert_eq!(count, 25);
+    }
+
+    #[test]
+    fn transforms_are_row_major() {
+        let source = "type: pbrm
+lighting:
+    background: 0 0 0
+    environment: null
+    skybox: off
+    shadow: on
+    ambient: 1.0
+    punctual: 0.5
+materials 0:
+transforms 1:
+    transform 0:
+        1 0 0 5
+        0 1 0 6
+        0 0 1 7
+        0 0 0 1
+models 0:
+";
+        let scene = Scene::parse(source, "test.scn").unwrap();
+        assert!(scene.lighting.shadow);
+        assert_eq!(scene.lighting.environment, None);
+        let p = scene.transforms[0] * Vector4::new(0.0, 0.0, 0.0, 1.0);
+        assert_eq!(p, Vector4::new(5.0, 6.0, 7.0, 1.0));
+    }
+
+    #[test]
+    fn blinn_materials_have_optional_fields() {
+        let material = "        basecolor: 1 1 1 1
+        shininess: 32
+        diffuse_map: null
+        specular_map: null
+        emission_map: null
+        double_sided: off
+        enable_blend: off
+        alpha_cutoff: 0
+";
+        let source = format!(
+            "type: blinn
+lighting:
+    background: 0 0 0
+    environment: null
+    skybox: off
+    shadow: off
+    ambient: 1.0
+    punctual: 1.0
+materials 2:
+    material 0:
+{}    material 1:
+{}        specular: 0.5 0.25 0
+        normal_map: normal.tga
+transforms 0:
+models 0:
+",
+            material, material
+        );
+        let scene = Scene::parse(&source, "dir/test.scn").unwrap();
+        let blinn = |index: usize| match &scene.materials[index] {
+            MaterialDesc::Blinn(desc) => desc.clone(),
+            other => panic!("{:?}", other),
+        };
+        assert_eq!(blinn(0).specular, BlinnMaterialDesc::DEFAULT_SPECULAR);
+        assert_eq!(blinn(0).normal_map, None);
+        assert_eq!(blinn(1).specular, Vector3::new(0.5, 0.25, 0.0));
+        assert!(blinn(1).normal_map.is_some_and(|path| path.ends_with("normal.tga")));
+    }
+}
@@ -0,0 +1,308 @@
+use crate::framebuffer::FrameBuffer;
+use crate::ibl::Environment;
+use crate::light::Light;
+use crate::material::{Material, Model, NormalFormat};
+use crate::matrix4::Matrix4;
+use crate::mesh::{Mesh, Vertex};
+use crate::pbr::{pbr_surface, PbrShader};
+use crate::renderer::{Fragment, Interpolate, Program, Renderer};
+use crate::shadow::ShadowMap;
+use crate::store::Store;
+use crate::texture::Filter;
+use crate::vector2::Vector2;
+use crate::vector3::Vector3;
+use crate::vector4::Vector4;
+
+// State shared by the scene shaders for one draw call, lighting happens in
+// view space.
+pub struct Uniforms<'a> {
+    pub mv: Matrix4,
+    pub projection: Matrix4,
+    pub light: Light,
+    pub material: &'a Material,
+    pub store: &'a Store,
+    pub joint_matrices: Option<&'a [Matrix4]>,
+    pub environment: Option<&'a Environment>,
+    // rotates view space directions back into the environment's space
+    pub view_to_world: Matrix4,
+    pub shadow_map: Option<&'a ShadowMap>,
+    // replaces the filter of every texture's sampler
+    pub filter: Option<Filter>,
+    // replaces the normal map format of the material
+    pub normal_format: Option<NormalFormat>,
+}
+
+#[derive(Copy, Clone)]
+pub struct Varyings {
+    pub tex_coord: Vector2,
+    pub normal: Vector3,
+    pub position: Vector3,
+    // view space tangent and bitangent sign, zero without tangents
+    pub tangent: Vector4,
+}
+
+impl Interpolate for Varyings {
+    fn add(&self, other: &Varyings) -> Varyings {
+        Varyings {
+            tex_coord: self.tex_coord + other.tex_coord,
+            normal: self.normal + other.normal,
+            position: self.position + other.position,
+            tangent: self.tangent + other.tangent,
+        }
+    }
+
+    fn scale(&self, factor: f32) -> Varyings {
+        Varyings {
+            tex_coord: self.tex_coord * factor,
+            normal: self.normal * factor,
+            position: self.position * factor,
+            tangent: self.tangent * factor,
+        }
+    }
+}
+
+// Linear blend of the joint matrices influencing a vertex.
+pub fn skin_matrix(vertex: &Vertex, joint_matrices: &[Matrix4]) -> Matrix4 {
+    let mut skin = Matrix4::new();
+    let mut total = 0.0;
+    for (&joint, &weight) in vertex.joints.iter().zip(vertex.weights.iter()) {
+        // influences of joints missing from the skeleton are dropped
+        if let Some(&matrix) = joint_matrices.get(joint as usize).filter(|_| weight > 0.0) {
+            skin = skin + matrix * weight;
+            total += weight;
+        }
+    }
+    if total > 0.0 {
+        skin * (1.0 / total)
+    } else {
+        Matrix4::identity()
+    }
+}
+
+// Vertex stage shared by the scene shaders: skinning, then view space
+// position, normal and tangent.
+pub fn transform_vertex(vertex: &Vertex, uniforms: &Uniforms) -> (Vector4, Varyings) {
+    let mut model_pos = Vector4::from_vector3(vertex.position);
+    let mut model_normal = Vector4::new(vertex.normal.x, vertex.normal.y, vertex.normal.z, 0.0);
+    let mut model_tangent = Vector4::new(vertex.tangent.x, vertex.tangent.y, vertex.tangent.z, 0.0);
+    if let Some(joint_matrices) = uniforms.joint_matrices {
+        let skin = skin_matrix(vertex, joint_matrices);
+        model_pos = skin * model_pos;
+        model_normal = skin * model_normal;
+        model_tangent = skin * model_tangent;
+    }
+    let view_pos = uniforms.mv * model_pos;
+    let tangent = (uniforms.mv * model_tangent).xyz();
+    let varyings = Varyings {
+        tex_coord: vertex.tex_coord,
+        normal: (uniforms.mv * model_normal).xyz().normalize(),
+        position: view_pos.xyz(),
+        tangent: Vector4::new(tangent.x, tangent.y, tangent.z, vertex.tangent.w),
+    };
+    (uniforms.projection * view_pos, varyings)
+}
+
+// Texture coordinates of a fragment with their screen space derivatives,
+// which select the mip levels.
+#[derive(Copy, Clone)]
+pub struct TexCoord {
+    pub uv: Vector2,
+    pub ddx: Vector2,
+    pub ddy: Vector2,
+}
+
+impl TexCoord {
+    pub fn new<V: Interpolate>(fragment: &Fragment<V>, tex_coord: impl Fn(&V) -> Vector2) -> TexCoord {
+        TexCoord {
+            uv: tex_coord(fragment),
+            ddx: fragment.ddx(&tex_coord),
+            ddy: fragment.ddy(&tex_coord),
+        }
+    }
+}
+
+pub fn sample_map(uniforms: &Uniforms, map: Option<u32>, uv: &TexCoord) -> Option<Vector4> {
+    map.map(|id| {
+        let mut sampler = *uniforms.store.get_sampler(id);
+        if let Some(filter) = uniforms.filter {
+            sampler.filter = filter;
+        }
+        uniforms.store.get_texture(id).sample_grad(&sampler, uv.uv, uv.ddx, uv.ddy)
+    })
+}
+
+pub fn emission(uniforms: &Uniforms, uv: &TexCoord) -> Vector3 {
+    let material = uniforms.material;
+    material.emission_factor
+        * sample_map(uniforms, material.emission_map, uv).map_or(Vector3::new(1.0, 1.0, 1.0), |c| c.xyz())
+}
+
+pub fn albedo(uniforms: &Uniforms, uv: &TexCoord) -> Vector3 {
+    let material = uniforms.material;
+    let mut albedo = material.basecolor_factor.xyz();
+    if let Some(c) = sample_map(uniforms, material.basecolor_map, uv) {
+        albedo = albedo * c.xyz();
+    }
+    albedo
+}
+
+// View space normal of a fragment, bent by the material's normal map when
+// the mesh has tangents.
+pub fn shading_normal(uniforms: &Uniforms, varyings: &Varyings, uv: &TexCoord) -> Vector3 {
+    let normal = varyings.normal.normalize();
+    let sample = match sample_map(uniforms, uniforms.material.normal_map, uv) {
+        Some(sample) if varyings.tangent.w != 0.0 => sample,
+        _ => return normal,
+    };
+    let mut perturbed = sample.xyz() * 2.0 - Vector3::new(1.0, 1.0, 1.0);
+    if uniforms.normal_format.unwrap_or(uniforms.material.normal_format) == NormalFormat::DirectX {
+        perturbed.y = -perturbed.y;
+    }
+    let tangent = varyings.tangent.xyz();
+    let tangent = tangent - normal * normal.dot(tangent);
+    if tangent.length_squared() == 0.0 {
+        return normal;
+    }
+    let tangent = tangent.normalize();
+    let bitangent = normal.cross(tangent) * varyings.tangent.w.signum();
+    (tangent * perturbed.x + bitangent * perturbed.y + normal * perturbed.z).normalize()
+}
+
+// Colors above one are kept for HDR output, the 8-bit outputs clamp them.
+pub fn opaque(color: Vector3) -> Vector4 {
+    Vector4::new(color.x.max(0.0), color.y.max(0.0), color.z.max(0.0), 1.0)
+}
+
+// Classic Blinn-Phong. Physically based materials get an exponent derived
+// from their roughness and a Fresnel-less specular color.
+pub struct BlinnShader;
+
+impl Program for BlinnShader {
+    type Uniforms<'a> = Uniforms<'a>;
+    type Varyings = Varyings;
+
+    fn vertex(&self, vertex: &Vertex, uniforms: &Uniforms) -> (Vector4, Varyings) {
+        transform_vertex(vertex, uniforms)
+    }
+
+    fn fragment(&self, varyings: &Fragment<Varyings>, uniforms: &Uniforms) -> Vector4 {
+        let material = uniforms.material;
+        let uv = TexCoord::new(varyings, |v| v.tex_coord);
+        let normal = shading_normal(uniforms, varyings, &uv);
+        let albedo = albedo(uniforms, &uv);
+        let (diffuse, specular, shininess) = match material.model {
+            Model::BlinnPhong => {
+                let specular = material.specular_factor
+                    * sample_map(uniforms, material.specular_map, &uv).map_or(Vector3::new(1.0, 1.0, 1.0), |c| c.xyz());
+                (albedo, specular, material.shininess)
+            }
+            // the exponent whose lobe is about as wide as the roughness'
+            Model::MetallicRoughness | Model::SpecularGlossiness => {
+                let (diffuse, specular, roughness) = pbr_surface(uniforms, &uv, albedo);
+                let alpha = (roughness * roughness).max(0.01);
+                (diffuse, specular, 2.0 / (alpha * alpha) - 2.0)
+            }
+        };
+        let ambient = match uniforms.environment {
+            Some(environment) => {
+                let n = (uniforms.view_to_world * Vector4::new(normal.x, normal.y, normal.z, 0.0)).xyz();
+                environment.diffuse(n)
+            }
+            None => Vector3::new(1.0, 1.0, 1.0),
+        };
+        let mut color = albedo * ambient * uniforms.light.ambient + emission(uniforms, &uv);
+
+        let mut l = uniforms.light.transform.position - varyings.position;
+        let distance_sqr = l.length_squared();
+        l = l.normalize();
+        let n_dot_l = normal.dot(l);
+        if n_dot_l > 0.0 {
+            let visibility = uniforms.shadow_map.map_or(1.0, |shadow_map| shadow_map.visibility(varyings.position));
+            let v = (-varyings.position).normalize();
+            let h = (v + l).normalize();
+            let highlight = normal.dot(h).max(0.0).powf(shininess);
+            let radiance = uniforms.light.intensity * (visibility / distance_sqr);
+            color = color + (diffuse * n_dot_l + specular * highlight) * radiance;
+        }
+        opaque(color)
+    }
+}
+
+// Base color and emission only, the varyings are just the texture
+// coordinates.
+pub struct UnlitShader;
+
+impl Program for UnlitShader {
+    type Uniforms<'a> = Uniforms<'a>;
+    type Varyings = Vector2;
+
+    fn vertex(&self, vertex: &Vertex, uniforms: &Uniforms) -> (Vector4, Vector2) {
+        let (position, varyings) = transform_vertex(vertex, uniforms);
+        (position, varyings.tex_coord)
+    }
+
+    fn fragment(&self, uv: &Fragment<Vector2>, uniforms: &Uniforms) -> Vector4 {
+        let uv = TexCoord::new(uv, |&uv| uv);
+        opaque(albedo(uniforms, &uv) + emission(uniforms, &uv))
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum DebugView {
+    // view space normals mapped to [0, 1]
+    Normal,
+    // fractional part of the texture coordinates in red and green
+    TexCoord,
+}
+
+pub struct DebugShader {
+    pub view: DebugView,
+}
+
+impl Program for DebugShader {
+    type Uniforms<'a> = Uniforms<'a>;
+    type Varyings = Varyings;
+
+    fn vertex(&self, vertex: &Vertex, uniforms: &Uniforms) -> (Vector4, Varyings) {
+        transform_vertex(vertex, uniforms)
+    }
+
+    fn fragment(&self, varyings: &Fragment<Varyings>, _: &Uniforms) -> Vector4 {
+        let color = match self.view {
+            DebugView::Normal => varyings.normal.normalize() * 0.5 + Vector3::new(0.5, 0.5, 0.5),
+            DebugView::TexCoord => {
+                let uv = varyings.tex_coord;
+                Vector3::new(uv.x - uv.x.floor(), uv.y - uv.y.floor(), 0.0)
+            }
+        };
+        opaque(color)
+    }
+}
+
+// Picks one of the scene shaders at run time.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Shading {
+    // each material with the shader of its own model
+    Material,
+    Pbr,
+    Blinn,
+    Unlit,
+    Debug(DebugView),
+}
+
+impl Shading {
+    pub fn draw(self, renderer: &Renderer, mesh: &Mesh, uniforms: &Uniforms, target: &mut FrameBuffer) {
+        match self {
+            Shading::Material => match uniforms.material.model {
+                Model::MetallicRoughness | Model::SpecularGlossiness => {
+                    Shading::Pbr.draw(renderer, mesh, uniforms, target)
+                }
+                Model::BlinnPhong => Shading::Blinn.draw(renderer, mesh, uniforms, target),
+            },
+            Shading::Pbr => renderer.draw(mesh, &PbrShader, uniforms, target),
+            Shading::Blinn => renderer.draw(mesh, &BlinnShader, uniforms, target),
+            Shading::Unlit => renderer.draw(mesh, &UnlitShader, uniforms, target),
+            Shading::Debug(view) => renderer.draw(mesh, &DebugShader { view }, uniforms, target),
+        }
+    }
+}
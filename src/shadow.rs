@@ -0,0 +1,204 @@
+use crate::framebuffer::FrameBuffer;
+use crate::light::Light;
+use crate::matrix4::Matrix4;
+use crate::mesh::{Mesh, Vertex};
+use crate::renderer::{Fragment, Program, Renderer};
+use crate::shader::skin_matrix;
+use crate::vector3::Vector3;
+use crate::vector4::Vector4;
+
+struct DepthUniforms<'a> {
+    // from the mesh's space to the light's clip space
+    light_mvp: Matrix4,
+    joint_matrices: Option<&'a [Matrix4]>,
+}
+
+// Writes only depth, the colors of the shadow map's buffer are unused.
+struct DepthProgram;
+
+impl Program for DepthProgram {
+    type Uniforms<'a> = DepthUniforms<'a>;
+    type Varyings = ();
+
+    fn vertex(&self, vertex: &Vertex, uniforms: &DepthUniforms) -> (Vector4, ()) {
+        let mut position = Vector4::from_vector3(vertex.position);
+        if let Some(joint_matrices) = uniforms.joint_matrices {
+            position = skin_matrix(vertex, joint_matrices) * position;
+        }
+        (uniforms.light_mvp * position, ())
+    }
+
+    fn fragment(&self, _: &Fragment<()>, _: &DepthUniforms) -> Vector4 {
+        Vector4::new(0.0, 0.0, 0.0, 1.0)
+    }
+}
+
+// Depth of the surfaces closest to a light, seen through a perspective
+// frustum from its position.
+pub struct ShadowMap {
+    pub size: u32,
+    // depth in [0, 1] as written by the renderer
+    buffer: FrameBuffer,
+    // maps the same space the shaded positions are in to the light's clip space
+    pub light_matrix: Matrix4,
+    // subtracted from the depth of the shaded point to avoid self shadowing
+    pub bias: f32,
+    // PCF averages (2 * radius + 1)^2 depth tests
+    pub pcf_radius: u32,
+}
+
+impl ShadowMap {
+    pub fn new(size: u32, bias: f32, pcf_radius: u32) -> ShadowMap {
+        ShadowMap {
+            size,
+            buffer: FrameBuffer::new(size, size),
+            light_matrix: Matrix4::identity(),
+            bias,
+            pcf_radius,
+        }
+    }
+
+    // Fits the frustum along the light's forward axis around the bounding
+    // sphere of the shadow casters. It is tightest when the light looks at
+    // the center of the sphere.
+    pub fn fit(&mut self, light: &Light, center: Vector3, radius: f32) {
+        let position = light.transform.position;
+        let distance = (center - position).length().max(radius * 1.01);
+        let forward = light.forward();
+        let to_center = ((center - position) * (1.0 / distance)).dot(forward).clamp(-1.0, 1.0).acos();
+        let fov = ((to_center + (radius / distance).asin()) * 2.0).min(170.0_f32.to_radians());
+        let near = (distance - radius).max(distance * 0.01);
+        let far = distance + radius;
+        self.light_matrix = Matrix4::perspective(fov, 1.0, near, far) * light.view_matrix();
+    }
+
+    pub fn clear(&mut self) {
+        self.buffer.clear(Vector4::new(0.0, 0.0, 0.0, 1.0));
+    }
+
+    pub fn get_depth(&self, x: u32, y: u32) -> f32 {
+        self.buffer.get_depth(x, y)
+    }
+
+    pub fn set_depth(&mut self, x: u32, y: u32, depth: f32) {
+        self.buffer.set_depth(x, y, depth);
+    }
+
+    // Renders the depth of a shadow caster, `mv` takes it to the space the
+    // shaded positions are in. Back faces cast shadows too, the culling of
+    // `renderer` is left as it was.
+    pub fn draw(&mut self, renderer: &mut Renderer, mesh: &Mesh, mv: Matrix4, joint_matrices: Option<&[Matrix4]>) {
+        let uniforms = DepthUniforms {
+            light_mvp: self.light_matrix * mv,
+            joint_matrices,
+        };
+        let cull_back_faces = std::mem::replace(&mut renderer.cull_back_faces, false);
+        renderer.draw(mesh, &DepthProgram, &uniforms, &mut self.buffer);
+        renderer.cull_back_faces = cull_back_faces;
+    }
+
+    // Fraction of the PCF kernel around `position` that the light reaches.
+    pub fn visibility(&self, position: Vector3) -> f32 {
+        let clip = self.light_matrix * Vector4::from_vector3(position);
+        if clip.w <= 0.0 {
+            return 1.0;
+        }
+        let ndc = clip.xyz() * (1.0 / clip.w);
+        // same mapping as the renderer's viewport and depth range
+        let size = self.size as f32;
+        let x = ((ndc.x + 1.0) * 0.5 * size).floor() as i64;
+        let y = ((1.0 - ndc.y) * 0.5 * size).floor() as i64;
+        let depth = ndc.z * 0.5 + 0.5 - self.bias;
+        let radius = self.pcf_radius as i64;
+        let last = self.size as i64 - 1;
+        let mut lit = 0;
+        for dy in -radius..=radius {
+            for dx in -radius..=radius {
+                let (sx, sy) = (x + dx, y + dy);
+                // nothing outside of the frustum casts shadows
+                if sx < 0 || sy < 0 || sx > last || sy > last || depth <= self.get_depth(sx as u32, sy as u32) {
+                    lit += 1;
+                }
+            }
+        }
+        lit as f32 / ((2 * radius + 1) * (2 * radius + 1)) as f32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transform::Transform;
+    use crate::vector2::Vector2;
+
+    #[test]
+    fn occluded_points_are_in_shadow() {
+        let mut shadow_map = ShadowMap::new(4, 0.01, 0);
+        assert_eq!(shadow_map.visibility(Vector3::new(0.0, 0.0, 0.5)), 1.0);
+        for y in 0..4 {
+            for x in 0..4 {
+                shadow_map.set_depth(x, y, 0.0);
+            }
+        }
+        assert_eq!(shadow_map.visibility(Vector3::new(0.0, 0.0, 0.5)), 0.0);
+        // beyond the edges of the map everything is lit
+        assert_eq!(shadow_map.visibility(Vector3::new(2.0, 0.0, 0.5)), 1.0);
+    }
+
+    #[test]
+    fn larger_kernels_widen_the_penumbra() {
+        // the left half of the map is covered by a caster
+        let penumbra = |pcf_radius| {
+            let mut shadow_map = ShadowMap::new(16, 0.01, pcf_radius);
+            for y in 0..16 {
+                for x in 0..8 {
+                    shadow_map.set_depth(x, y, 0.0);
+                }
+            }
+            // away from the edges, where everything is lit
+            let visibility: Vec<f32> = (3..13)
+                .map(|x| shadow_map.visibility(Vector3::new((x as f32 + 0.5) / 8.0 - 1.0, 0.0, 0.5)))
+                .collect();
+            assert!(visibility.windows(2).all(|pair| pair[0] <= pair[1]));
+            visibility.iter().filter(|&&v| v > 0.0 && v < 1.0).count()
+        };
+        assert_eq!(penumbra(0), 0);
+        assert_eq!(penumbra(1), 2);
+        assert_eq!(penumbra(2), 4);
+    }
+
+    #[test]
+    fn casters_are_rendered_from_both_sides() {
+        // a clockwise quad in the middle of the light's clip space
+        let mut mesh = Mesh::new();
+        for (x, y) in [(-0.5, -0.5), (-0.5, 0.5), (0.5, 0.5), (0.5, -0.5)] {
+            mesh.vertices.push(Vertex::new(Vector3::new(x, y, 0.0), Vector2::new(0.0, 0.0), Vector3::zero()));
+        }
+        mesh.indices = vec![0, 1, 2, 0, 2, 3];
+        let mut shadow_map = ShadowMap::new(8, 0.01, 0);
+        let mut renderer = Renderer::new();
+        shadow_map.draw(&mut renderer, &mesh, Matrix4::identity(), None);
+        assert!(renderer.cull_back_faces);
+        assert_eq!(shadow_map.visibility(Vector3::new(0.0, 0.0, 0.5)), 0.0);
+        assert_eq!(shadow_map.visibility(Vector3::new(0.0, 0.0, -0.5)), 1.0);
+        assert_eq!(shadow_map.visibility(Vector3::new(0.9, 0.0, 0.5)), 1.0);
+    }
+
+    #[test]
+    fn frustum_is_fitted_around_the_casters() {
+        let mut light = Light::new(Vector3::zero(), Vector3::zero(), Transform::identity());
+        light.transform.position = Vector3::new(3.0, 0.0, 0.0);
+        light.look_at(Vector3::zero());
+        let mut shadow_map = ShadowMap::new(4, 0.01, 0);
+        shadow_map.fit(&light, Vector3::zero(), 1.0);
+        let ndc = |p: Vector3| {
+            let clip = shadow_map.light_matrix * Vector4::from_vector3(p);
+            clip.xyz() * (1.0 / clip.w)
+        };
+        let center = ndc(Vector3::zero());
+        assert!(center.x.abs() < 1e-5 && center.y.abs() < 1e-5);
+        // the sphere touches the sides of the map
+        let top = ndc(Vector3::new(0.0, 1.0, 0.0));
+        assert!(top.x.abs() < 1e-5 && top.y.abs() > 0.9 && top.y.abs() <= 1.0);
+    }
+}
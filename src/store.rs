@@ -1,3 +1,65 @@
+use crate::error::LoadError;
+use crate::texture::{Sampler, Texture};
+use std::collections::HashMap;
+
+// Owns every image loaded for a scene so that materials sharing a map
+// reference a single copy by index. A texture id is an image read through
+// a sampler, several ids can share an image.
 pub struct Store {
+    images: Vec<Texture>,
+    textures: Vec<(u32, Sampler)>,
+    // keyed by path and whether the image is sRGB
+    texture_ids: HashMap<(String, bool), u32>,
+}
+
+impl Default for Store {
+    fn default() -> Store {
+        Store::new()
+    }
+}
+
+impl Store {
+    pub fn new() -> Store {
+        Store {
+            images: Vec::new(),
+            textures: Vec::new(),
+            texture_ids: HashMap::new(),
+        }
+    }
+
+    pub fn load_texture(&mut self, path: &str, srgb: bool) -> Result<u32, LoadError> {
+        let key = (path.to_string(), srgb);
+        if let Some(&id) = self.texture_ids.get(&key) {
+            return Ok(id);
+        }
+        let id = self.add_texture(Texture::load(path, srgb)?);
+        self.texture_ids.insert(key, id);
+        Ok(id)
+    }
+
+    // Adds a texture that does not come from its own file, e.g. one
+    // embedded in a glTF binary. It is never shared by path.
+    pub fn add_texture(&mut self, texture: Texture) -> u32 {
+        self.images.push(texture);
+        self.textures.push((self.images.len() as u32 - 1, Sampler::new()));
+        self.textures.len() as u32 - 1
+    }
+
+    // Returns the id of the image of texture `id` read through `sampler`.
+    pub fn with_sampler(&mut self, id: u32, sampler: Sampler) -> u32 {
+        let binding = (self.textures[id as usize].0, sampler);
+        if let Some(existing) = self.textures.iter().position(|texture| *texture == binding) {
+            return existing as u32;
+        }
+        self.textures.push(binding);
+        self.textures.len() as u32 - 1
+    }
+
+    pub fn get_texture(&self, id: u32) -> &Texture {
+        &self.images[self.textures[id as usize].0 as usize]
+    }
 
-}
\ No newline at end of file
+    pub fn get_sampler(&self, id: u32) -> &Sampler {
+        &self.textures[id as usize].1
+    }
+}
@@ -0,0 +1,277 @@
+use crate::mesh::Mesh;
+use crate::vector3::Vector3;
+use crate::vector4::Vector4;
+use std::collections::HashMap;
+
+// Tangent of one triangle as MikkTSpace sees it, with v pointing up the
+// image like in the tools baking the normal maps.
+struct Face {
+    // direction of increasing u
+    tangent: Vector3,
+    // u and v turn the same way as the winding, false on mirrored texture
+    preserving: bool,
+    // no texture area, the triangle takes the orientation of a neighbour
+    // and adds nothing to the tangents
+    any: bool,
+    // two corners at the same position, left out entirely
+    degenerate: bool,
+}
+
+// Sum of the tangents of the triangles sharing a vertex across edges.
+#[derive(Copy, Clone)]
+struct Group {
+    tangent: Vector3,
+    preserving: Option<bool>,
+}
+
+fn normalize_or_zero(v: Vector3) -> Vector3 {
+    if v.length_squared() > 0.0 {
+        v.normalize()
+    } else {
+        v
+    }
+}
+
+fn find(parent: &mut [usize], mut i: usize) -> usize {
+    while parent[i] != i {
+        parent[i] = parent[parent[i]];
+        i = parent[i];
+    }
+    i
+}
+
+fn union(parent: &mut [usize], a: usize, b: usize) {
+    let (a, b) = (find(parent, a), find(parent, b));
+    parent[a] = b;
+}
+
+impl Mesh {
+    // Per vertex tangents and handedness following MikkTSpace, the
+    // convention of the tools that bake normal maps. Vertices whose corners
+    // end up with different tangents, e.g. where a mirrored texture meets
+    // itself, are split.
+    pub fn generate_tangents(&mut self) {
+        // vertices are welded by value so that corners indexed separately
+        // still share edges
+        let mut ids = HashMap::new();
+        let welded: Vec<usize> = self
+            .vertices
+            .iter()
+            .map(|vertex| {
+                let p = vertex.position;
+                let n = vertex.normal;
+                let t = vertex.tex_coord;
+                let key = [p.x, p.y, p.z, n.x, n.y, n.z, t.x, t.y].map(f32::to_bits);
+                let next = ids.len();
+                *ids.entry(key).or_insert(next)
+            })
+            .collect();
+        let triangles: Vec<[usize; 3]> = self.indices.chunks_exact(3).map(|t| [t[0], t[1], t[2]]).collect();
+        let faces: Vec<Face> = triangles
+            .iter()
+            .map(|triangle| {
+                let [a, b, c] = triangle.map(|i| &self.vertices[i]);
+                let (d1, d2) = (b.position - a.position, c.position - a.position);
+                let (t21x, t21y) = (b.tex_coord.x - a.tex_coord.x, a.tex_coord.y - b.tex_coord.y);
+                let (t31x, t31y) = (c.tex_coord.x - a.tex_coord.x, a.tex_coord.y - c.tex_coord.y);
+                let area = t21x * t31y - t21y * t31x;
+                let sign = if area > 0.0 { 1.0 } else { -1.0 };
+                Face {
+                    tangent: normalize_or_zero(d1 * t31y - d2 * t21y) * sign,
+                    preserving: area > 0.0,
+                    any: area == 0.0,
+                    degenerate: a.position == b.position || a.position == c.position || b.position == c.position,
+                }
+            })
+            .collect();
+        let corner_vertex = |corner: usize| welded[triangles[corner / 3][corner % 3]];
+
+        // corners of a vertex are grouped across the edges of triangles with
+        // the same orientation, texture seams and mirroring split the groups
+        let mut edges = HashMap::new();
+        for (t, face) in faces.iter().enumerate() {
+            if !face.degenerate {
+                for c in 0..3 {
+                    edges.entry((corner_vertex(t * 3 + c), corner_vertex(t * 3 + (c + 1) % 3))).or_insert(t);
+                }
+            }
+        }
+        let shared_corners = |t: usize, c: usize| {
+            let (a, b) = (corner_vertex(t * 3 + c), corner_vertex(t * 3 + (c + 1) % 3));
+            let u = *edges.get(&(b, a))?;
+            let corner_of = |vertex| (0..3).map(|i| u * 3 + i).find(|&corner| corner_vertex(corner) == vertex);
+            Some((u, [(t * 3 + c, corner_of(a)?), (t * 3 + (c + 1) % 3, corner_of(b)?)]))
+        };
+        let mut parent: Vec<usize> = (0..faces.len() * 3).collect();
+        for (t, face) in faces.iter().enumerate() {
+            if face.degenerate || face.any {
+                continue;
+            }
+            for c in 0..3 {
+                if let Some((u, pairs)) = shared_corners(t, c) {
+                    if u != t && !faces[u].any && faces[u].preserving == face.preserving {
+                        for (a, b) in pairs {
+                            union(&mut parent, a, b);
+                        }
+                    }
+                }
+            }
+        }
+        // triangles without texture area join the first group around them
+        // and keep its orientation
+        for (t, face) in faces.iter().enumerate() {
+            if face.degenerate || !face.any {
+                continue;
+            }
+            let mut orientation = None;
+            for c in 0..3 {
+                if let Some((u, pairs)) = shared_corners(t, c) {
+                    if faces[u].any || orientation.is_some_and(|preserving| preserving != faces[u].preserving) {
+                        continue;
+                    }
+                    orientation = Some(faces[u].preserving);
+                    for (a, b) in pairs {
+                        union(&mut parent, a, b);
+                    }
+                }
+            }
+        }
+
+        // face tangents projected on the vertex normal, weighted by the
+        // angle of the triangle at the vertex
+        let mut groups = vec![
+            Group {
+                tangent: Vector3::zero(),
+                preserving: None,
+            };
+            parent.len()
+        ];
+        for (t, face) in faces.iter().enumerate() {
+            if face.degenerate || face.any {
+                continue;
+            }
+            for c in 0..3 {
+                let vertex = &self.vertices[triangles[t][c]];
+                let normal = normalize_or_zero(vertex.normal);
+                let project = |v: Vector3| normalize_or_zero(v - normal * normal.dot(v));
+                let position = |i: usize| self.vertices[triangles[t][i % 3]].position;
+                let to_previous = project(position(c + 2) - vertex.position);
+                let to_next = project(position(c + 1) - vertex.position);
+                let angle = to_previous.dot(to_next).clamp(-1.0, 1.0).acos();
+                let group = &mut groups[find(&mut parent, t * 3 + c)];
+                group.tangent = group.tangent + project(face.tangent) * angle;
+                group.preserving = Some(face.preserving);
+            }
+        }
+        let mut corners: Vec<Option<Vector4>> = (0..parent.len())
+            .map(|corner| {
+                if faces[corner / 3].degenerate {
+                    return None;
+                }
+                let group = groups[find(&mut parent, corner)];
+                Some(match group.preserving {
+                    Some(preserving) => {
+                        let tangent = normalize_or_zero(group.tangent);
+                        Vector4::new(tangent.x, tangent.y, tangent.z, if preserving { 1.0 } else { -1.0 })
+                    }
+                    None => Vector4::new(1.0, 0.0, 0.0, 1.0),
+                })
+            })
+            .collect();
+        // corners of degenerate triangles copy another corner of their vertex
+        let mut by_vertex = HashMap::new();
+        for (corner, tangent) in corners.iter().enumerate() {
+            if let Some(tangent) = tangent {
+                by_vertex.entry(corner_vertex(corner)).or_insert(*tangent);
+            }
+        }
+        for (corner, tangent) in corners.iter_mut().enumerate() {
+            if tangent.is_none() {
+                *tangent = Some(*by_vertex.get(&corner_vertex(corner)).unwrap_or(&Vector4::new(1.0, 0.0, 0.0, 1.0)));
+            }
+        }
+
+        let mut assigned = vec![false; self.vertices.len()];
+        let mut splits = HashMap::new();
+        for (corner, tangent) in corners.into_iter().enumerate() {
+            let tangent = tangent.unwrap();
+            let index = self.indices[corner];
+            if !assigned[index] {
+                assigned[index] = true;
+                self.vertices[index].tangent = tangent;
+            } else if self.vertices[index].tangent != tangent {
+                let key = (index, [tangent.x, tangent.y, tangent.z, tangent.w].map(f32::to_bits));
+                let vertices = &mut self.vertices;
+                self.indices[corner] = *splits.entry(key).or_insert_with(|| {
+                    let mut vertex = vertices[index];
+                    vertex.tangent = tangent;
+                    vertices.push(vertex);
+                    vertices.len() - 1
+                });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mesh::Vertex;
+    use crate::vector2::Vector2;
+
+    fn vertex(x: f32, y: f32, u: f32, v: f32) -> Vertex {
+        Vertex::new(Vector3::new(x, y, 0.0), Vector2::new(u, v), Vector3::new(0.0, 0.0, 1.0))
+    }
+
+    #[test]
+    fn mirrored_halves_are_split() {
+        // two quads sharing the edge at x = 0, the left one textured with
+        // the mirror image of the right one
+        let mut mesh = Mesh::new();
+        mesh.vertices = vec![
+            vertex(0.0, 0.0, 0.0, 1.0),
+            vertex(0.0, 1.0, 0.0, 0.0),
+            vertex(1.0, 0.0, 1.0, 1.0),
+            vertex(1.0, 1.0, 1.0, 0.0),
+            vertex(-1.0, 0.0, 1.0, 1.0),
+            vertex(-1.0, 1.0, 1.0, 0.0),
+        ];
+        mesh.indices = vec![0, 2, 3, 0, 3, 1, 0, 1, 5, 0, 5, 4];
+        mesh.generate_tangents();
+        // the shared vertices get a copy for each side
+        assert_eq!(mesh.vertices.len(), 8);
+        for (corner, &index) in mesh.indices.iter().enumerate() {
+            let tangent = mesh.vertices[index].tangent;
+            if corner < 6 {
+                assert_eq!(tangent, Vector4::new(1.0, 0.0, 0.0, 1.0));
+            } else {
+                assert_eq!(tangent, Vector4::new(-1.0, 0.0, 0.0, -1.0));
+            }
+        }
+    }
+
+    #[test]
+    fn tangents_follow_texture_coordinates() {
+        // v is flipped on load, the image's up is +y here
+        let source = "v 0 0 0
+v 1 0 0
+v 1 1 0
+vt 0 0
+vt 1 0
+vt 1 1
+vn 0 0 1
+f 1/1/1 2/2/1 3/3/1
+";
+        let mut mesh = Mesh::from_obj_reader(source.as_bytes(), "test.obj").unwrap();
+        assert!(!mesh.has_tangents());
+        mesh.generate_tangents();
+        for vertex in &mesh.vertices {
+            assert_eq!(vertex.tangent, Vector4::new(1.0, 0.0, 0.0, 1.0));
+        }
+        // mirrored texture coordinates flip the bitangent
+        let mirrored = source.replace("vt 1 1", "vt 1 -1");
+        let mut mesh = Mesh::from_obj_reader(mirrored.as_bytes(), "test.obj").unwrap();
+        mesh.generate_tangents();
+        assert_eq!(mesh.vertices[0].tangent.w, -1.0);
+    }
+}
@@ -1,52 +1,274 @@
 use crate::{vector4::Vector4, vector2::Vector2};
-use image;
-use crate::math::srgb_to_linear;
+use crate::math::{linear_to_srgb, srgb_to_linear};
+use crate::error::LoadError;
+use image::RgbaImage;
 
+// How texels are picked and blended, from the mip level closest to the
+// footprint of the fragment unless the filter blends two of them.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Filter {
+    Nearest,
+    Bilinear,
+    // bilinear in the two closest levels, blended
+    Trilinear,
+    // up to the given number of trilinear samples along the longer axis
+    // of the footprint, at most 16
+    Anisotropic(u32),
+}
+
+pub const MAX_ANISOTROPY: u32 = 16;
+
+// What happens to texture coordinates outside of [0, 1].
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum AddressMode {
+    Repeat,
+    // repeats, flipping every other copy
+    MirroredRepeat,
+    ClampToEdge,
+    // texels outside of the image have the sampler's border color
+    ClampToBorder,
+}
+
+impl AddressMode {
+    // Texel index for `coord` in an axis of `size` texels, None for the
+    // border.
+    fn apply(self, coord: i64, size: u32) -> Option<u32> {
+        let size = size as i64;
+        let coord = match self {
+            AddressMode::Repeat => coord.rem_euclid(size),
+            AddressMode::MirroredRepeat => {
+                let coord = coord.rem_euclid(size * 2);
+                if coord < size { coord } else { size * 2 - 1 - coord }
+            }
+            AddressMode::ClampToEdge => coord.clamp(0, size - 1),
+            AddressMode::ClampToBorder if (0..size).contains(&coord) => coord,
+            AddressMode::ClampToBorder => return None,
+        };
+        Some(coord as u32)
+    }
+}
+
+// How a texture is read: addressing per axis, filtering and an offset to
+// the level of detail computed from the derivatives.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Sampler {
+    pub address_u: AddressMode,
+    pub address_v: AddressMode,
+    // linear color of the border, alpha included
+    pub border_color: Vector4,
+    pub filter: Filter,
+    pub lod_bias: f32,
+}
+
+impl Default for Sampler {
+    fn default() -> Sampler {
+        Sampler::new()
+    }
+}
 
+impl Sampler {
+    pub fn new() -> Sampler {
+        Sampler {
+            address_u: AddressMode::Repeat,
+            address_v: AddressMode::Repeat,
+            border_color: Vector4::new(0.0, 0.0, 0.0, 0.0),
+            filter: Filter::Trilinear,
+            lod_bias: 0.0,
+        }
+    }
+}
 
 #[derive(Debug)]
 pub struct Texture {
-    pub image: image::RgbaImage,
+    pub image: RgbaImage,
     pub width: u32,
     pub height: u32,
+    // color maps are stored in sRGB and decoded when sampled, data such as
+    // normal maps is stored as is
+    pub srgb: bool,
+    // levels 1 and up of the mip chain, each half the size of the previous
+    mips: Vec<RgbaImage>,
+}
+
+// Halves an image with a box filter, colors are averaged in linear space.
+fn downsample(image: &RgbaImage, srgb: bool) -> RgbaImage {
+    let decode = |c: usize, value: f32| if srgb && c < 3 { srgb_to_linear(value) } else { value };
+    let encode = |c: usize, value: f32| if srgb && c < 3 { linear_to_srgb(value) } else { value };
+    let (width, height) = ((image.width() / 2).max(1), (image.height() / 2).max(1));
+    RgbaImage::from_fn(width, height, |x, y| {
+        let mut sum = [0.0; 4];
+        for (dx, dy) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+            let pixel = image.get_pixel((x * 2 + dx).min(image.width() - 1), (y * 2 + dy).min(image.height() - 1));
+            for c in 0..4 {
+                let value = pixel[c] as f32 / 255.0;
+                sum[c] += decode(c, value);
+            }
+        }
+        let channel = |c: usize| {
+            (encode(c, sum[c] * 0.25) * 255.0).round() as u8
+        };
+        image::Rgba([channel(0), channel(1), channel(2), channel(3)])
+    })
 }
 
 impl Texture {
-    pub fn new(image: image::RgbaImage, width: u32, height: u32) -> Texture {
+    pub fn new(image: RgbaImage, width: u32, height: u32, srgb: bool) -> Texture {
+        let mut mips: Vec<RgbaImage> = Vec::new();
+        loop {
+            let last = mips.last().unwrap_or(&image);
+            if last.width() == 1 && last.height() == 1 {
+                break;
+            }
+            mips.push(downsample(last, srgb));
+        }
         Texture {
             image,
             width,
             height,
+            srgb,
+            mips,
         }
     }
 
 
-    pub fn load(path: &str) -> Texture {
-        let image = image::open(path).unwrap();
+    pub fn load(path: &str, srgb: bool) -> Result<Texture, LoadError> {
+        let image = image::open(path).map_err(|e| LoadError::image(path, e))?;
+        let width = image.width();
+        let height = image.height();
+        Ok(Texture::new(image.into_rgba8(), width, height, srgb))
+    }
+
+    // Decodes an image embedded in another file, `path` is only used to
+    // report errors.
+    pub fn decode(bytes: &[u8], path: &str, srgb: bool) -> Result<Texture, LoadError> {
+        let image = image::load_from_memory(bytes).map_err(|e| LoadError::image(path, e))?;
         let width = image.width();
         let height = image.height();
-        Texture::new(image.into_rgba8(), width, height)
+        Ok(Texture::new(image.into_rgba8(), width, height, srgb))
+    }
+
+    // Number of levels in the mip chain, the full size image included.
+    pub fn levels(&self) -> usize {
+        self.mips.len() + 1
+    }
+
+    fn level(&self, level: usize) -> &RgbaImage {
+        match level {
+            0 => &self.image,
+            _ => &self.mips[level - 1],
+        }
     }
 
     pub fn get_pixel(&self, x: u32, y: u32) -> Vector4 {
-        let pixel = self.image.get_pixel(x, y);
-        
+        self.get_texel(0, x, y)
+    }
+
+    fn get_texel(&self, level: usize, x: u32, y: u32) -> Vector4 {
+        let pixel = self.level(level).get_pixel(x, y);
+        if !self.srgb {
+            return Vector4::new(pixel[0] as f32, pixel[1] as f32, pixel[2] as f32, pixel[3] as f32) * (1.0 / 255.0);
+        }
+
+        // alpha is linear, as in `downsample`
         Vector4::new(
             srgb_to_linear(pixel[0] as f32 / 255.0),
             srgb_to_linear(pixel[1] as f32 / 255.0),
             srgb_to_linear(pixel[2] as f32 / 255.0),
-            srgb_to_linear(pixel[3] as f32 / 255.0),
+            pixel[3] as f32 / 255.0,
         )
     }
 
+    // Texel of `level` at integer coordinates, addressed by the sampler.
+    fn fetch(&self, sampler: &Sampler, level: usize, x: i64, y: i64) -> Vector4 {
+        let image = self.level(level);
+        match (sampler.address_u.apply(x, image.width()), sampler.address_v.apply(y, image.height())) {
+            (Some(x), Some(y)) => self.get_texel(level, x, y),
+            _ => sampler.border_color,
+        }
+    }
+
+    fn nearest(&self, sampler: &Sampler, level: usize, uv: Vector2) -> Vector4 {
+        let image = self.level(level);
+        let x = (uv.x * image.width() as f32).floor() as i64;
+        let y = (uv.y * image.height() as f32).floor() as i64;
+        self.fetch(sampler, level, x, y)
+    }
+
+    // Texel centers are at half integers, the four around `uv` are blended.
+    fn bilinear(&self, sampler: &Sampler, level: usize, uv: Vector2) -> Vector4 {
+        let image = self.level(level);
+        let x = uv.x * image.width() as f32 - 0.5;
+        let y = uv.y * image.height() as f32 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as i64, y0 as i64);
+        let fetch = |x, y| self.fetch(sampler, level, x, y);
+        let top = fetch(x0, y0) * (1.0 - fx) + fetch(x0 + 1, y0) * fx;
+        let bottom = fetch(x0, y0 + 1) * (1.0 - fx) + fetch(x0 + 1, y0 + 1) * fx;
+        top * (1.0 - fy) + bottom * fy
+    }
+
+    fn trilinear(&self, sampler: &Sampler, lod: f32, uv: Vector2) -> Vector4 {
+        let lower = lod.floor() as usize;
+        let upper = (lower + 1).min(self.levels() - 1);
+        let t = lod - lower as f32;
+        if t == 0.0 || upper == lower {
+            return self.bilinear(sampler, lower, uv);
+        }
+        self.bilinear(sampler, lower, uv) * (1.0 - t) + self.bilinear(sampler, upper, uv) * t
+    }
+
+    // Level of detail for a fragment whose texture coordinates change by
+    // `ddx` and `ddy` to the next pixels, before any anisotropic sampling.
+    pub fn lod(&self, ddx: Vector2, ddy: Vector2) -> f32 {
+        let size = Vector2::new(self.width as f32, self.height as f32);
+        let footprint = (ddx * size).length().max((ddy * size).length());
+        footprint.max(f32::MIN_POSITIVE).log2()
+    }
+
+    // Samples at an explicit level of detail, the sampler's bias is not
+    // added. Anisotropic filtering falls back to trilinear without
+    // derivatives.
+    pub fn sample_lod(&self, sampler: &Sampler, uv: Vector2, lod: f32) -> Vector4 {
+        let lod = lod.clamp(0.0, (self.levels() - 1) as f32);
+        match sampler.filter {
+            Filter::Nearest => self.nearest(sampler, lod.round() as usize, uv),
+            Filter::Bilinear => self.bilinear(sampler, lod.round() as usize, uv),
+            Filter::Trilinear | Filter::Anisotropic(_) => self.trilinear(sampler, lod, uv),
+        }
+    }
+
+    // Samples with the level of detail selected from the derivatives of the
+    // texture coordinates, offset by the sampler's bias.
+    pub fn sample_grad(&self, sampler: &Sampler, uv: Vector2, ddx: Vector2, ddy: Vector2) -> Vector4 {
+        let bias = sampler.lod_bias;
+        let max_anisotropy = match sampler.filter {
+            Filter::Anisotropic(max) => max.clamp(1, MAX_ANISOTROPY),
+            _ => return self.sample_lod(sampler, uv, self.lod(ddx, ddy) + bias),
+        };
+        // the level follows the shorter axis of the footprint and samples
+        // are spread along the longer one
+        let size = Vector2::new(self.width as f32, self.height as f32);
+        let (x_length, y_length) = ((ddx * size).length(), (ddy * size).length());
+        let (major, major_length, minor_length) = if x_length >= y_length {
+            (ddx, x_length, y_length)
+        } else {
+            (ddy, y_length, x_length)
+        };
+        let count = (major_length / minor_length.max(f32::MIN_POSITIVE)).ceil().clamp(1.0, max_anisotropy as f32);
+        let lod = (major_length / count).max(f32::MIN_POSITIVE).log2() + bias;
+        let lod = lod.clamp(0.0, (self.levels() - 1) as f32);
+        let mut sum = Vector4::new(0.0, 0.0, 0.0, 0.0);
+        for i in 0..count as u32 {
+            let offset = (i as f32 + 0.5) / count - 0.5;
+            sum = sum + self.trilinear(sampler, lod, uv + major * offset);
+        }
+        sum * (1.0 / count)
+    }
+
+    // Bilinear lookup in the full size image, repeating the texture.
     pub fn sample(&self, uv: Vector2) -> Vector4 {
-        let u = uv.x;
-        let v = uv.y;
-        let u = u - u.floor();
-        let v = v - v.floor();
-        let c = (u * (self.width - 1) as f32) as u32;
-        let r = (v * (self.height - 1) as f32) as u32;
-        self.get_pixel(c, r)
+        self.bilinear(&Sampler::new(), 0, uv)
     }
 
 
@@ -54,6 +276,91 @@ impl Texture {
 
 impl Clone for Texture {
     fn clone(&self) -> Texture {
-        Texture::new(self.image.clone(), self.width, self.height)
+        Texture {
+            image: self.image.clone(),
+            width: self.width,
+            height: self.height,
+            srgb: self.srgb,
+            mips: self.mips.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // one texel wide black and white columns
+    fn stripes(size: u32) -> Texture {
+        let image = RgbaImage::from_fn(size, size, |x, _| {
+            let value = if x % 2 == 0 { 255 } else { 0 };
+            image::Rgba([value, value, value, 255])
+        });
+        Texture::new(image, size, size, true)
+    }
+
+    #[test]
+    fn alpha_is_linear_in_every_level() {
+        let image = RgbaImage::from_pixel(2, 2, image::Rgba([128, 128, 128, 128]));
+        let texture = Texture::new(image, 2, 2, true);
+        let nearest = Sampler {
+            filter: Filter::Nearest,
+            ..Sampler::new()
+        };
+        let uv = Vector2::new(0.5, 0.5);
+        let (full, smallest) = (texture.sample_lod(&nearest, uv, 0.0), texture.sample_lod(&nearest, uv, 1.0));
+        assert_eq!(full.w, 128.0 / 255.0);
+        assert_eq!(smallest.w, full.w);
+        assert!(full.x < 0.25);
+    }
+
+    #[test]
+    fn mip_chain_and_lod() {
+        let texture = stripes(8);
+        assert_eq!(texture.levels(), 4);
+        // the stripes average to middle gray in linear space
+        let nearest = Sampler {
+            filter: Filter::Nearest,
+            ..Sampler::new()
+        };
+        let gray = texture.sample_lod(&nearest, Vector2::new(0.3, 0.6), 3.0);
+        assert!((gray.x - 0.5).abs() < 0.01);
+        // one texel per pixel selects the full size image, four the third
+        let texel = 1.0 / 8.0;
+        assert_eq!(texture.lod(Vector2::new(texel, 0.0), Vector2::new(0.0, texel)), 0.0);
+        assert_eq!(texture.lod(Vector2::new(texel * 4.0, 0.0), Vector2::new(0.0, texel)), 2.0);
+        // a footprint stretched along the stripes blurs them unless the
+        // samples are spread along it
+        let uv = Vector2::new(0.5 / 8.0, 0.5 / 8.0);
+        let (ddx, ddy) = (Vector2::new(texel, 0.0), Vector2::new(0.0, texel * 4.0));
+        let blurred = texture.sample_grad(&Sampler::new(), uv, ddx, ddy);
+        assert!((blurred.x - 0.5).abs() < 0.01);
+        let anisotropic = Sampler {
+            filter: Filter::Anisotropic(4),
+            ..Sampler::new()
+        };
+        let sharp = texture.sample_grad(&anisotropic, uv, ddx, ddy);
+        assert_eq!(sharp.x, 1.0);
+    }
+
+    #[test]
+    fn address_modes() {
+        let texture = stripes(8);
+        let mut sampler = Sampler {
+            filter: Filter::Nearest,
+            ..Sampler::new()
+        };
+        // u = -0.05 falls in the last column, or the first when mirrored
+        let left = Vector2::new(-0.05, 0.5);
+        assert_eq!(texture.sample_lod(&sampler, left, 0.0).x, 0.0);
+        sampler.address_u = AddressMode::MirroredRepeat;
+        assert_eq!(texture.sample_lod(&sampler, left, 0.0).x, 1.0);
+        sampler.address_u = AddressMode::ClampToEdge;
+        assert_eq!(texture.sample_lod(&sampler, left, 0.0).x, 1.0);
+        sampler.address_u = AddressMode::ClampToBorder;
+        sampler.border_color = Vector4::new(0.25, 0.5, 0.75, 1.0);
+        assert_eq!(texture.sample_lod(&sampler, left, 0.0), sampler.border_color);
+        // v is still repeated
+        assert_eq!(texture.sample_lod(&sampler, Vector2::new(0.05, 7.5), 0.0).x, 1.0);
     }
 }
@@ -35,13 +35,10 @@ impl Transform {
         self.rotation * (self.scale * vector)
     }
 
-    pub fn to_mat4(&self) -> Matrix4 {
-        let mut x = self.rotation * Vector3::new(1.0, 0.0, 0.0);
-        let mut y = self.rotation * Vector3::new(0.0, 1.0, 0.0);
-        let mut z = self.rotation * Vector3::new(0.0, 0.0, 1.0);
-        x = self.scale * x;
-        y = self.scale * y;
-        z = self.scale * z;
+    pub fn to_mat4(self) -> Matrix4 {
+        let x = self.rotation * Vector3::new(self.scale.x, 0.0, 0.0);
+        let y = self.rotation * Vector3::new(0.0, self.scale.y, 0.0);
+        let z = self.rotation * Vector3::new(0.0, 0.0, self.scale.z);
 
         let p = self.position;
         Matrix4::from_array([
@@ -9,7 +9,7 @@ pub struct Vector2 {
 
 impl Vector2 {
     pub fn new(x: f32, y: f32) -> Vector2 {
-        Vector2 { x: x, y: y }
+        Vector2 { x, y }
     }
 
     pub fn dot(&self, other: Vector2) -> f32 {
@@ -18,6 +18,10 @@ impl Vector2 {
     pub fn cross(&self, other: Vector2) -> f32 {
         self.x * other.y - self.y * other.x
     }
+
+    pub fn length(&self) -> f32 {
+        self.dot(*self).sqrt()
+    }
 }
 
 impl Mul for Vector2 {
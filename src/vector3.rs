@@ -9,8 +9,8 @@ pub struct Vector3 {
 }
 
 impl Vector3 {
-    pub fn new(x: f32, y: f32, z: f32) -> Vector3 {
-        Vector3 { x: x, y: y, z: z }
+    pub const fn new(x: f32, y: f32, z: f32) -> Vector3 {
+        Vector3 { x, y, z }
     }
 
     pub fn zero() -> Vector3 {
@@ -54,7 +54,7 @@ impl Vector3 {
         self.x * other.x + self.y * other.y + self.z * other.z
     }
 
-    pub fn to_u32(&self) -> u32 {
+    pub fn to_u32(self) -> u32 {
         let r = (self.x * 255.0) as u32;
         let g = (self.y * 255.0) as u32;
         let b = (self.z * 255.0) as u32;
@@ -13,10 +13,10 @@ pub struct Vector4 {
 impl Vector4 {
     pub fn new(x: f32, y: f32, z: f32, w: f32) -> Vector4 {
         Vector4 {
-            x: x,
-            y: y,
-            z: z,
-            w: w,
+            x,
+            y,
+            z,
+            w,
         }
     }
 
@@ -24,11 +24,11 @@ impl Vector4 {
         self.x * rhs.x + self.y * rhs.y + self.z * rhs.z + self.w * rhs.w
     }
 
-    pub fn to_u32(&self) -> u32 {
-        let r = (self.x * 255.0) as u32;
-        let g = (self.y * 255.0) as u32;
-        let b = (self.z * 255.0) as u32;
-        let a = (self.w * 255.0) as u32;
+    pub fn to_u32(self) -> u32 {
+        let r = (self.x.clamp(0.0, 1.0) * 255.0) as u32;
+        let g = (self.y.clamp(0.0, 1.0) * 255.0) as u32;
+        let b = (self.z.clamp(0.0, 1.0) * 255.0) as u32;
+        let a = (self.w.clamp(0.0, 1.0) * 255.0) as u32;
         (r << 16) | (g << 8) | b | (a << 24)
     }
 
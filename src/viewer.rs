@@ -0,0 +1,80 @@
+use minifb::{Key, Window, WindowOptions};
+use trois::camera::Camera;
+use trois::error::LoadError;
+use trois::framebuffer::FrameBuffer;
+use trois::quat::Quat;
+use trois::renderer::Renderer;
+use trois::shader::{DebugView, Shading};
+use trois::transform::Transform;
+use trois::vector3::Vector3;
+use trois::world::World;
+
+// Window size of the viewer, every other size comes from the framebuffer.
+const WIDTH: usize = 640;
+const HEIGHT: usize = 480;
+
+fn exit_with(error: LoadError) -> ! {
+    eprintln!("error: {}", error);
+    std::process::exit(1);
+}
+
+pub fn run(scene_path: &str) {
+    let world = World::load(scene_path).unwrap_or_else(|e| exit_with(e));
+    for warning in &world.warnings {
+        eprintln!("warning: {}", warning);
+    }
+
+    let camera = Camera::new(
+        Vector3::new(0.0, 0.0, 2.5),
+        Vector3::new(0.0, 0.0, 0.0),
+        Vector3::new(0.0, 1.0, 0.0),
+        45.0 / 180.0 * std::f32::consts::PI,
+        WIDTH as f32 / HEIGHT as f32,
+        0.1,
+        100.0,
+    );
+
+    let mut stage = world.stage(&camera).unwrap_or_else(|e| exit_with(e));
+    let mut framebuffer = FrameBuffer::new(WIDTH as u32, HEIGHT as u32);
+
+    let mut window = Window::new(
+        "Test - ESC to exit",
+        WIDTH,
+        HEIGHT,
+        WindowOptions::default(),
+    )
+    .unwrap_or_else(|e| {
+        panic!("{}", e);
+    });
+
+    let mut renderer = Renderer::new();
+    let mut angle = 0.0;
+    let mut root = Transform::identity();
+    let clock = std::time::Instant::now();
+
+    while window.is_open() && !window.is_key_down(Key::Escape) {
+
+        for key in window.get_keys_released() {
+            stage.shading = match key {
+                Key::Key1 => Shading::Material,
+                Key::Key2 => Shading::Pbr,
+                Key::Key3 => Shading::Blinn,
+                Key::Key4 => Shading::Unlit,
+                Key::Key5 => Shading::Debug(DebugView::Normal),
+                Key::Key6 => Shading::Debug(DebugView::TexCoord),
+                _ => stage.shading,
+            };
+        }
+
+        angle += 0.1;
+        root.rotation = Quat::angle_axis(angle, &Vector3::new(0.0, 1.0, 0.0));
+        let time = clock.elapsed().as_secs_f32();
+        world.draw(&mut stage, &camera, &mut renderer, root.to_mat4(), time, &mut framebuffer);
+
+
+
+        window
+            .update_with_buffer(&framebuffer.srgb_colors(), WIDTH, HEIGHT)
+            .unwrap();
+    }
+}
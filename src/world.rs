@@ -0,0 +1,607 @@
+use crate::animation::Skeleton;
+use crate::camera::Camera;
+use crate::error::LoadError;
+use crate::framebuffer::FrameBuffer;
+use crate::ibl::Environment;
+use crate::light::Light;
+use crate::loader::Asset;
+use crate::material::{Material, NormalFormat};
+use crate::matrix4::Matrix4;
+use crate::mesh::{Mesh, ObjModel};
+use crate::mtl::load_mtl;
+use crate::renderer::Renderer;
+use crate::scene::{Lighting, Scene, Skybox};
+use crate::shader::{skin_matrix, Shading, Uniforms};
+use crate::shadow::ShadowMap;
+use crate::store::Store;
+use crate::texture::Filter;
+use crate::transform::Transform;
+use crate::vector3::Vector3;
+use crate::vector4::Vector4;
+use std::collections::HashMap;
+use std::path::Path;
+
+const SHADOW_SIZE: u32 = 1024;
+// defaults of the shadow map's depth bias and PCF kernel radius
+pub const SHADOW_BIAS: f32 = 0.001;
+pub const SHADOW_PCF_RADIUS: u32 = 1;
+
+// A mesh drawn with one material, possibly skinned or attached to a joint.
+pub struct Model {
+    pub mesh: Mesh,
+    pub material: usize,
+    pub transform: Matrix4,
+    pub skeleton: Option<usize>,
+    // joint the whole model follows rigidly instead of being skinned
+    pub attached: Option<usize>,
+}
+
+impl Model {
+    pub fn model_matrix(&self, joint_matrices: &[Vec<Matrix4>]) -> Matrix4 {
+        match (self.skeleton, self.attached) {
+            (Some(skeleton), Some(joint)) => self.transform * joint_matrices[skeleton][joint],
+            _ => self.transform,
+        }
+    }
+
+    pub fn skinning<'a>(&self, joint_matrices: &'a [Vec<Matrix4>]) -> Option<&'a [Matrix4]> {
+        match (self.skeleton, self.attached) {
+            (Some(skeleton), None) => Some(&joint_matrices[skeleton]),
+            _ => None,
+        }
+    }
+}
+
+// Loads every model of the scene, sharing skeletons between models that
+// reference the same animation file.
+fn load_models(scene: &Scene) -> Result<(Vec<Model>, Vec<Skeleton>), LoadError> {
+    let mut skeletons = Vec::new();
+    let mut skeleton_ids: HashMap<String, usize> = HashMap::new();
+    let mut models = Vec::new();
+    for desc in &scene.models {
+        let skeleton = match &desc.skeleton {
+            Some(path) => Some(match skeleton_ids.get(path) {
+                Some(&id) => id,
+                None => {
+                    skeletons.push(Skeleton::load(path)?);
+                    skeleton_ids.insert(path.clone(), skeletons.len() - 1);
+                    skeletons.len() - 1
+                }
+            }),
+            None => None,
+        };
+        let mesh = Mesh::from_obj_file(&desc.mesh)?;
+        let attached = match skeleton {
+            Some(skeleton) if desc.attached >= 0 => Some(desc.attached as usize)
+                .filter(|&joint| joint < skeletons[skeleton].joints.len()),
+            _ => None,
+        };
+        models.push(Model {
+            mesh,
+            material: desc.material,
+            transform: scene.transforms[desc.transform],
+            skeleton,
+            attached,
+        });
+    }
+    Ok((models, skeletons))
+}
+
+type Loaded = (Lighting, Vec<Material>, Vec<Model>, Vec<Skeleton>);
+
+fn load_scene(path: &str, store: &mut Store) -> Result<Loaded, LoadError> {
+    let scene = Scene::load(path)?;
+    let materials = scene
+        .materials
+        .iter()
+        .map(|desc| Material::from_desc(desc, store))
+        .collect::<Result<_, _>>()?;
+    let (models, skeletons) = load_models(&scene)?;
+    Ok((scene.lighting, materials, models, skeletons))
+}
+
+// Every primitive of a glTF file becomes a model, primitives without a
+// material use the glTF default one appended after the file's materials.
+fn load_gltf(path: &str, store: &mut Store) -> Result<Loaded, LoadError> {
+    let asset = Asset::load(path, store)?;
+    let mut models = Vec::new();
+    for (index, node) in asset.nodes.iter().enumerate() {
+        let mesh = match node.mesh {
+            Some(mesh) => mesh,
+            None => continue,
+        };
+        let transform = match node.skin {
+            Some(_) => Matrix4::identity(),
+            None => asset.world_matrix(index),
+        };
+        for primitive in &asset.meshes[mesh] {
+            models.push(Model {
+                mesh: primitive.mesh.clone(),
+                material: primitive.material.unwrap_or(asset.materials.len()),
+                transform,
+                skeleton: node.skin,
+                attached: None,
+            });
+        }
+    }
+    let mut materials = asset.materials;
+    materials.push(Material::new());
+    Ok((Lighting::default(), materials, models, asset.skins))
+}
+
+// Every material of a bare OBJ becomes a model, faces without a known
+// material use a default one appended after those of the MTL files.
+fn load_obj(path: &str, store: &mut Store, warnings: &mut Vec<LoadError>) -> Result<Loaded, LoadError> {
+    let obj = ObjModel::load(path)?;
+    let mut names = Vec::new();
+    let mut materials = Vec::new();
+    for library in &obj.material_libraries {
+        match load_mtl(library) {
+            Ok(descs) => {
+                for (name, desc) in descs {
+                    names.push(name);
+                    materials.push(Material::from_desc(&desc, store)?);
+                }
+            }
+            // downloaded models often come without their MTL files
+            Err(error @ LoadError::Io { .. }) => warnings.push(error),
+            Err(error) => return Err(error),
+        }
+    }
+    // without a material library, maps named like the tinyrenderer models
+    // are picked up from next to the OBJ file
+    let sibling = |suffix: &str| {
+        let file = format!("{}_{}", path.trim_end_matches(".obj"), suffix);
+        Path::new(&file).exists().then_some(file)
+    };
+    let default = materials.len();
+    materials.push(Material {
+        metallic_factor: 0.0,
+        basecolor_map: sibling("diffuse.tga").map(|file| store.load_texture(&file, true)).transpose()?,
+        normal_map: sibling("nm_tangent.tga").map(|file| store.load_texture(&file, false)).transpose()?,
+        ..Material::new()
+    });
+    let models = obj
+        .submeshes
+        .into_iter()
+        .map(|submesh| Model {
+            material: submesh
+                .material
+                .and_then(|material| names.iter().position(|name| *name == material))
+                .unwrap_or(default),
+            mesh: submesh.mesh,
+            transform: Matrix4::identity(),
+            skeleton: None,
+            attached: None,
+        })
+        .collect();
+    Ok((Lighting::default(), materials, models, Vec::new()))
+}
+
+// How a world is lit and shaded when seen from one camera, built once and
+// reused for every frame.
+pub struct Stage {
+    pub environment: Option<Environment>,
+    // the punctual light, in view space
+    pub light: Light,
+    // for scenes with shadows, its bias and PCF radius can be changed
+    pub shadow_map: Option<ShadowMap>,
+    // prefiltered level shown behind the models, as a roughness
+    pub skybox: Option<f32>,
+    pub background: Vector4,
+    // fits the world in the unit sphere around the origin
+    pub normalize: Matrix4,
+    pub shading: Shading,
+    // overrides the filters of the texture samplers
+    pub filter: Option<Filter>,
+    // overrides the green channel direction of every normal map
+    pub normal_format: Option<NormalFormat>,
+}
+
+// Everything needed to draw a scene, whatever file it came from.
+pub struct World {
+    pub lighting: Lighting,
+    pub materials: Vec<Material>,
+    pub models: Vec<Model>,
+    pub skeletons: Vec<Skeleton>,
+    pub store: Store,
+    // problems that did not stop the loading, for the caller to report
+    pub warnings: Vec<LoadError>,
+}
+
+impl World {
+    // Loads a glTF file, a bare OBJ or a .scn scene based on the extension.
+    pub fn load(path: &str) -> Result<World, LoadError> {
+        let mut store = Store::new();
+        let mut warnings = Vec::new();
+        let (lighting, materials, mut models, skeletons) = if path.ends_with(".gltf") || path.ends_with(".glb") {
+            load_gltf(path, &mut store)
+        } else if path.ends_with(".obj") {
+            load_obj(path, &mut store, &mut warnings)
+        } else {
+            load_scene(path, &mut store)
+        }?;
+        // normal maps need a tangent frame, assets without one get it from
+        // their texture coordinates
+        for model in &mut models {
+            if materials[model.material].normal_map.is_some() && !model.mesh.has_tangents() {
+                model.mesh.generate_tangents();
+            }
+        }
+        Ok(World {
+            lighting,
+            materials,
+            models,
+            skeletons,
+            store,
+            warnings,
+        })
+    }
+
+    pub fn joint_matrices(&self, time: f32) -> Vec<Vec<Matrix4>> {
+        self.skeletons
+            .iter()
+            .map(|skeleton| skeleton.joint_matrices(time))
+            .collect()
+    }
+
+    // Maps the bounding sphere of all models onto the unit sphere at the
+    // origin, so that every scene fits the same camera regardless of its units.
+    pub fn normalize_matrix(&self, joint_matrices: &[Vec<Matrix4>]) -> Matrix4 {
+        let mut min = Vector3::new(f32::MAX, f32::MAX, f32::MAX);
+        let mut max = Vector3::new(f32::MIN, f32::MIN, f32::MIN);
+        for model in &self.models {
+            let model_matrix = model.model_matrix(joint_matrices);
+            let skinning = model.skinning(joint_matrices);
+            for vertex in &model.mesh.vertices {
+                let mut p = Vector4::from_vector3(vertex.position);
+                if let Some(skinning) = skinning {
+                    p = skin_matrix(vertex, skinning) * p;
+                }
+                let p = (model_matrix * p).xyz();
+                min = Vector3::new(min.x.min(p.x), min.y.min(p.y), min.z.min(p.z));
+                max = Vector3::new(max.x.max(p.x), max.y.max(p.y), max.z.max(p.z));
+            }
+        }
+        let center = (min + max) * 0.5;
+        let radius = (max - min).length() * 0.5;
+        if radius <= 0.0 {
+            return Matrix4::identity();
+        }
+        let scale = 1.0 / radius;
+        Matrix4::from_scaling(scale, scale, scale)
+            * Matrix4::from_translation(-center.x, -center.y, -center.z)
+    }
+
+    // Loads the environment and places the light and shadow map for a camera
+    // that looks at the normalized world.
+    pub fn stage(&self, camera: &Camera) -> Result<Stage, LoadError> {
+        let lighting = &self.lighting;
+        let environment = lighting.environment.as_ref().map(|dir| Environment::load(dir)).transpose()?;
+        // without an environment the ambient term is a dim constant
+        let ambient = match environment {
+            Some(_) => Vector3::new(1.0, 1.0, 1.0),
+            None => Vector3::new(0.1, 0.1, 0.1),
+        };
+        let skybox = match (&environment, lighting.skybox) {
+            (Some(_), Skybox::On) => Some(0.0),
+            (Some(_), Skybox::Blurred) => Some(0.4),
+            _ => None,
+        };
+        let mut light = Light::new(
+            ambient * lighting.ambient,
+            Vector3::new(5.0, 5.0, 5.0) * lighting.punctual,
+            Transform::identity(),
+        );
+        let view = camera.get_view_matrix();
+        light.transform.position = (view * Vector4::from_vector3(Vector3::new(0.0, 0.0, 3.0))).xyz();
+        // the normalized world is centered on the origin
+        let center = (view * Vector4::new(0.0, 0.0, 0.0, 1.0)).xyz();
+        light.look_at(center);
+        let shadow_map = lighting.shadow.then(|| {
+            let mut shadow_map = ShadowMap::new(SHADOW_SIZE, SHADOW_BIAS, SHADOW_PCF_RADIUS);
+            shadow_map.fit(&light, center, 1.0);
+            shadow_map
+        });
+        Ok(Stage {
+            environment,
+            light,
+            shadow_map,
+            skybox,
+            background: Vector4::from_vector3(lighting.background),
+            normalize: self.normalize_matrix(&self.joint_matrices(0.0)),
+            shading: Shading::Material,
+            filter: None,
+            normal_format: None,
+        })
+    }
+
+    // Clears `target` and draws the world with its animations at `time`
+    // seconds, `root` transforms the normalized world.
+    pub fn draw(
+        &self,
+        stage: &mut Stage,
+        camera: &Camera,
+        renderer: &mut Renderer,
+        root: Matrix4,
+        time: f32,
+        target: &mut FrameBuffer,
+    ) {
+        target.clear(stage.background);
+        let joint_matrices = self.joint_matrices(time);
+        let view = camera.get_view_matrix() * root * stage.normalize;
+
+        if let Some(shadow_map) = stage.shadow_map.as_mut() {
+            shadow_map.clear();
+            for model in &self.models {
+                let mv = view * model.model_matrix(&joint_matrices);
+                shadow_map.draw(renderer, &model.mesh, mv, model.skinning(&joint_matrices));
+            }
+        }
+
+        let view_to_world = camera.get_view_matrix().inverse();
+        for model in &self.models {
+            let uniforms = Uniforms {
+                mv: view * model.model_matrix(&joint_matrices),
+                projection: camera.get_projection_matrix(),
+                light: stage.light,
+                material: &self.materials[model.material],
+                store: &self.store,
+                joint_matrices: model.skinning(&joint_matrices),
+                environment: stage.environment.as_ref(),
+                view_to_world,
+                shadow_map: stage.shadow_map.as_ref(),
+                filter: stage.filter,
+                normal_format: stage.normal_format,
+            };
+            renderer.cull_back_faces = !uniforms.material.double_sided;
+            stage.shading.draw(renderer, &model.mesh, &uniforms, target);
+        }
+        if let (Some(environment), Some(blur)) = (&stage.environment, stage.skybox) {
+            environment.draw_skybox(target, blur, camera.get_projection_matrix(), view_to_world);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cubemap::{Cubemap, HdrImage};
+    use crate::mesh::Vertex;
+    use crate::texture::Texture;
+    use crate::vector2::Vector2;
+    use image::{Rgba, RgbaImage};
+
+    fn uniform_cubemap(color: Vector3) -> Cubemap {
+        let face = HdrImage {
+            width: 1,
+            height: 1,
+            pixels: vec![color],
+        };
+        Cubemap {
+            size: 1,
+            faces: vec![face; 6],
+        }
+    }
+
+    // A green quad in the middle of the view, drawn unlit.
+    fn quad_world() -> World {
+        let mut mesh = Mesh::new();
+        for (x, y) in [(-0.5, -0.5), (0.5, -0.5), (0.5, 0.5), (-0.5, 0.5)] {
+            mesh.vertices.push(Vertex::new(Vector3::new(x, y, 0.0), Vector2::new(0.0, 0.0), Vector3::new(0.0, 0.0, 1.0)));
+        }
+        mesh.indices = vec![0, 1, 2, 0, 2, 3];
+        World {
+            lighting: Lighting::default(),
+            materials: vec![Material {
+                basecolor_factor: Vector4::new(0.0, 1.0, 0.0, 1.0),
+                ..Material::new()
+            }],
+            models: vec![Model {
+                mesh,
+                material: 0,
+                transform: Matrix4::identity(),
+                skeleton: None,
+                attached: None,
+            }],
+            skeletons: Vec::new(),
+            store: Store::new(),
+            warnings: Vec::new(),
+        }
+    }
+
+    fn front_camera() -> Camera {
+        Camera::new(
+            Vector3::new(0.0, 0.0, 2.5),
+            Vector3::zero(),
+            Vector3::new(0.0, 1.0, 0.0),
+            std::f32::consts::FRAC_PI_4,
+            1.0,
+            0.1,
+            100.0,
+        )
+    }
+
+    // Draws `world` with the scene lighting, the light sits just behind the
+    // camera.
+    fn draw_lit(world: &World, shading: Shading) -> FrameBuffer {
+        let camera = front_camera();
+        let mut stage = world.stage(&camera).unwrap();
+        stage.normalize = Matrix4::identity();
+        stage.shading = shading;
+        let mut target = FrameBuffer::new(16, 16);
+        world.draw(&mut stage, &camera, &mut Renderer::new(), Matrix4::identity(), 0.0, &mut target);
+        target
+    }
+
+    // A 1x1 linear texture with every channel set to `value`.
+    fn gray_texture(store: &mut Store, value: u8) -> u32 {
+        let image = RgbaImage::from_pixel(1, 1, Rgba([value, value, value, 255]));
+        store.add_texture(Texture::new(image, 1, 1, false))
+    }
+
+    fn green(color: Vector4) -> i32 {
+        ((color.to_u32() >> 8) & 0xFF) as i32
+    }
+
+    #[test]
+    fn missing_mtl_files_are_warnings() {
+        let path = std::env::temp_dir().join(format!("trois-missing-mtl-{}.obj", std::process::id()));
+        std::fs::write(&path, "mtllib missing.mtl\nv 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 3\n").unwrap();
+        let world = World::load(&path.to_string_lossy());
+        std::fs::remove_file(&path).unwrap();
+        let world = world.unwrap();
+        assert_eq!(world.models.len(), 1);
+        assert!(matches!(world.warnings[..], [LoadError::Io { .. }]));
+    }
+
+    #[test]
+    fn skybox_fills_uncovered_pixels() {
+        let world = quad_world();
+        let camera = front_camera();
+        let sharp = Vector3::new(0.2, 0.4, 0.6);
+        let blurred = Vector3::new(0.8, 0.8, 0.8);
+        let mut stage = world.stage(&camera).unwrap();
+        stage.normalize = Matrix4::identity();
+        stage.shading = Shading::Unlit;
+        stage.background = Vector4::new(0.0, 0.0, 1.0, 1.0);
+        let draw = |stage: &mut Stage| {
+            let mut target = FrameBuffer::new(16, 16);
+            world.draw(stage, &camera, &mut Renderer::new(), Matrix4::identity(), 0.0, &mut target);
+            target
+        };
+
+        // without an environment the background shows through
+        let target = draw(&mut stage);
+        assert_eq!(target.get_color(0, 0).to_u32(), 0xFF0000FF);
+        assert_eq!(target.get_color(8, 8).to_u32(), 0xFF00FF00);
+
+        stage.environment = Some(Environment {
+            irradiance: uniform_cubemap(sharp),
+            specular: vec![uniform_cubemap(sharp), uniform_cubemap(blurred)],
+            brdf_lut: HdrImage {
+                width: 1,
+                height: 1,
+                pixels: vec![Vector3::zero()],
+            },
+        });
+        for (blur, color) in [(0.0, sharp), (1.0, blurred)] {
+            stage.skybox = Some(blur);
+            let target = draw(&mut stage);
+            assert_eq!(target.get_depth(0, 0), 1.0);
+            assert_eq!(target.get_color(0, 0).to_u32(), Vector4::from_vector3(color).to_u32());
+            // the model keeps its own shading
+            assert!(target.get_depth(8, 8) < 1.0);
+            assert_eq!(target.get_color(8, 8).to_u32(), 0xFF00FF00);
+        }
+    }
+
+    #[test]
+    fn blinn_highlights_follow_the_specular_map() {
+        let blinn = |specular: f32, shininess: f32, map: Option<u8>| {
+            let mut world = quad_world();
+            let specular_map = map.map(|value| gray_texture(&mut world.store, value));
+            world.materials[0] = Material {
+                model: crate::material::Model::BlinnPhong,
+                // black so that only the highlight is left
+                basecolor_factor: Vector4::new(0.0, 0.0, 0.0, 1.0),
+                specular_factor: Vector3::new(specular, specular, specular),
+                specular_map,
+                shininess,
+                ..Material::new()
+            };
+            let target = draw_lit(&world, Shading::Material);
+            (green(target.get_color(8, 8)), green(target.get_color(5, 8)))
+        };
+
+        // the highlight peaks in the middle, straight below the light
+        let (center, edge) = blinn(1.0, 8.0, None);
+        assert!(center > 100);
+        assert!(edge > 0 && edge < center);
+
+        // a zero specular factor has none
+        assert_eq!(blinn(0.0, 8.0, None), (0, 0));
+
+        // the map scales the highlight
+        let (half, _) = blinn(1.0, 8.0, Some(128));
+        assert!((half - center / 2).abs() <= 1);
+        assert_eq!(blinn(1.0, 8.0, Some(0)), (0, 0));
+
+        // and the shininess narrows it
+        let (sharp_center, sharp_edge) = blinn(1.0, 64.0, None);
+        assert!(sharp_edge * center < edge * sharp_center);
+    }
+
+    #[test]
+    fn specular_glossiness_matches_metallic_roughness() {
+        let draw = |material: Material| {
+            let mut world = quad_world();
+            world.materials[0] = material;
+            draw_lit(&world, Shading::Material)
+        };
+        let assert_close = |a: &FrameBuffer, b: &FrameBuffer| {
+            assert!(a.get_color(8, 8).to_u32() != 0xFF000000);
+            for (a, b) in a.get_colors().iter().zip(b.get_colors()) {
+                let (a, b) = (a.to_u32(), b.to_u32());
+                for shift in [0, 8, 16] {
+                    assert!((((a >> shift) & 0xFF) as i32 - ((b >> shift) & 0xFF) as i32).abs() <= 1);
+                }
+            }
+        };
+
+        // a dielectric reflects 4% and keeps the rest of its diffuse color
+        let base = Vector3::new(0.96, 0.48, 0.24);
+        let metallic_roughness = draw(Material {
+            basecolor_factor: Vector4::from_vector3(base),
+            metallic_factor: 0.0,
+            roughness_factor: 0.4,
+            ..Material::new()
+        });
+        let specular_glossiness = draw(Material {
+            model: crate::material::Model::SpecularGlossiness,
+            basecolor_factor: Vector4::from_vector3(base * (1.0 / 0.96)),
+            specular_factor: Vector3::new(0.04, 0.04, 0.04),
+            glossiness_factor: 0.6,
+            ..Material::new()
+        });
+        assert_close(&metallic_roughness, &specular_glossiness);
+
+        // a metal has no diffuse part and reflects its own color
+        let metallic_roughness = draw(Material {
+            basecolor_factor: Vector4::from_vector3(base),
+            metallic_factor: 1.0,
+            roughness_factor: 0.4,
+            ..Material::new()
+        });
+        let specular_glossiness = draw(Material {
+            model: crate::material::Model::SpecularGlossiness,
+            basecolor_factor: Vector4::new(0.0, 0.0, 0.0, 1.0),
+            specular_factor: base,
+            glossiness_factor: 0.6,
+            ..Material::new()
+        });
+        assert_close(&metallic_roughness, &specular_glossiness);
+    }
+
+    #[test]
+    fn blinn_shading_reads_the_pbr_maps() {
+        // red at the edge of the quad, away from the highlight in the middle
+        let edge = |metallic: u8, roughness: u8| {
+            let mut world = quad_world();
+            let metallic_map = Some(gray_texture(&mut world.store, metallic));
+            let roughness_map = Some(gray_texture(&mut world.store, roughness));
+            world.materials[0] = Material {
+                basecolor_factor: Vector4::new(1.0, 0.0, 0.0, 1.0),
+                metallic_map,
+                roughness_map,
+                ..Material::new()
+            };
+            let color = draw_lit(&world, Shading::Blinn).get_color(5, 8).to_u32();
+            (color >> 16 & 0xFF) as i32
+        };
+        // a rough metal spreads its highlight, a smooth one keeps it narrow
+        assert!(edge(255, 64) < edge(255, 255) / 4);
+        // a dielectric has a diffuse part
+        assert!(edge(0, 64) > edge(255, 64) * 4);
+    }
+}